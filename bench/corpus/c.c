@@ -0,0 +1,73 @@
+#include <errno.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define INITIAL_CAPACITY 16
+#define MAX_LINE 4096
+
+/* A growable array of strings. */
+typedef struct {
+    char **items;
+    size_t len;
+    size_t cap;
+} strvec;
+
+static int strvec_push(strvec *v, const char *s)
+{
+    if (v->len == v->cap) {
+        size_t cap = v->cap ? v->cap * 2 : INITIAL_CAPACITY;
+        char **items = realloc(v->items, cap * sizeof(*items));
+        if (items == NULL)
+            return -ENOMEM;
+        v->items = items;
+        v->cap = cap;
+    }
+    v->items[v->len] = strdup(s);
+    if (v->items[v->len] == NULL)
+        return -ENOMEM;
+    v->len++;
+    return 0;
+}
+
+static void strvec_free(strvec *v)
+{
+    for (size_t i = 0; i < v->len; i++)
+        free(v->items[i]);
+    free(v->items);
+    memset(v, 0, sizeof(*v));
+}
+
+static int compare(const void *a, const void *b)
+{
+    return strcmp(*(char *const *)a, *(char *const *)b);
+}
+
+int main(int argc, char **argv)
+{
+    FILE *f = argc > 1 ? fopen(argv[1], "r") : stdin;
+    char line[MAX_LINE];
+    strvec lines = {0};
+
+    if (f == NULL) {
+        fprintf(stderr, "%s: %s\n", argv[1], strerror(errno));
+        return EXIT_FAILURE;
+    }
+    while (fgets(line, sizeof(line), f) != NULL) {
+        line[strcspn(line, "\r\n")] = '\0';
+        if (strvec_push(&lines, line) < 0) {
+            fputs("out of memory\n", stderr);
+            return EXIT_FAILURE;
+        }
+    }
+    qsort(lines.items, lines.len, sizeof(char *), compare);
+    for (size_t i = 0; i < lines.len; i++) {
+        if (i > 0 && strcmp(lines.items[i], lines.items[i - 1]) == 0)
+            continue;
+        printf("%4zu: %s\n", i + 1, lines.items[i]);
+    }
+    strvec_free(&lines);
+    if (f != stdin)
+        fclose(f);
+    return 0;
+}
@@ -0,0 +1,116 @@
+// Package cache implements a small concurrency-safe LRU cache.
+package cache
+
+import (
+	"container/list"
+	"errors"
+	"fmt"
+	"sync"
+	"time"
+)
+
+// ErrNotFound is returned when a key is not present in the cache.
+var ErrNotFound = errors.New("cache: key not found")
+
+type entry struct {
+	key     string
+	value   interface{}
+	expires time.Time
+}
+
+// Cache is an LRU cache with optional per-entry expiry.
+type Cache struct {
+	mu       sync.Mutex
+	capacity int
+	ll       *list.List
+	items    map[string]*list.Element
+	onEvict  func(key string, value interface{})
+}
+
+// New returns a cache which holds at most capacity entries.
+func New(capacity int, onEvict func(string, interface{})) *Cache {
+	if capacity <= 0 {
+		panic(fmt.Sprintf("cache: invalid capacity %d", capacity))
+	}
+	return &Cache{
+		capacity: capacity,
+		ll:       list.New(),
+		items:    make(map[string]*list.Element, capacity),
+		onEvict:  onEvict,
+	}
+}
+
+// Get returns the value stored under key.
+func (c *Cache) Get(key string) (interface{}, error) {
+	c.mu.Lock()
+	defer c.mu.Unlock()
+
+	el, ok := c.items[key]
+	if !ok {
+		return nil, ErrNotFound
+	}
+	e := el.Value.(*entry)
+	if !e.expires.IsZero() && time.Now().After(e.expires) {
+		c.removeElement(el)
+		return nil, ErrNotFound
+	}
+	c.ll.MoveToFront(el)
+	return e.value, nil
+}
+
+// Set stores value under key, evicting the least recently used entry if the
+// cache is full. A ttl of zero means the entry never expires.
+func (c *Cache) Set(key string, value interface{}, ttl time.Duration) {
+	c.mu.Lock()
+	defer c.mu.Unlock()
+
+	var expires time.Time
+	if ttl > 0 {
+		expires = time.Now().Add(ttl)
+	}
+	if el, ok := c.items[key]; ok {
+		c.ll.MoveToFront(el)
+		e := el.Value.(*entry)
+		e.value, e.expires = value, expires
+		return
+	}
+	c.items[key] = c.ll.PushFront(&entry{key: key, value: value, expires: expires})
+	for c.ll.Len() > c.capacity {
+		c.removeElement(c.ll.Back())
+	}
+}
+
+func (c *Cache) removeElement(el *list.Element) {
+	c.ll.Remove(el)
+	e := el.Value.(*entry)
+	delete(c.items, e.key)
+	if c.onEvict != nil {
+		c.onEvict(e.key, e.value)
+	}
+}
+
+// Len returns the number of entries in the cache.
+func (c *Cache) Len() int {
+	c.mu.Lock()
+	defer c.mu.Unlock()
+	return c.ll.Len()
+}
+
+func main() {
+	c := New(2, func(k string, v interface{}) { fmt.Printf("evicted %q=%v\n", k, v) })
+	c.Set("a", 1, 0)
+	c.Set("b", 2, time.Second)
+	c.Set("c", 3, 0)
+	if _, err := c.Get("a"); err == ErrNotFound {
+		fmt.Println(`"a" was evicted`)
+	}
+	for i := 0; i < 10; i++ {
+		switch {
+		case i%2 == 0:
+			c.Set(fmt.Sprint(i), i*i, 0)
+		default:
+			_, _ = c.Get(fmt.Sprint(i - 1))
+		}
+	}
+	fmt.Println(c.Len(), 0x1F, 3.14e-2, 'x')
+}
@@ -0,0 +1,100 @@
+package com.example.inventory;
+
+import java.io.Serializable;
+import java.math.BigDecimal;
+import java.util.ArrayList;
+import java.util.Collections;
+import java.util.List;
+import java.util.Objects;
+import java.util.Optional;
+import java.util.stream.Collectors;
+
+/**
+ * An inventory of {@link Item}s, grouped by warehouse.
+ *
+ * @author someone
+ */
+public class Inventory implements Serializable {
+    private static final long serialVersionUID = 1L;
+    private static final int MAX_ITEMS = 10_000;
+
+    private final String warehouse;
+    private final List<Item> items = new ArrayList<>();
+
+    public Inventory(String warehouse) {
+        this.warehouse = Objects.requireNonNull(warehouse, "warehouse");
+    }
+
+    /** Adds an item, failing if the inventory is full. */
+    public synchronized void add(Item item) throws InventoryFullException {
+        if (items.size() >= MAX_ITEMS) {
+            throw new InventoryFullException("inventory of " + warehouse + " is full");
+        }
+        items.add(item);
+    }
+
+    public Optional<Item> find(String sku) {
+        return items.stream().filter(i -> i.getSku().equals(sku)).findFirst();
+    }
+
+    public BigDecimal totalValue() {
+        BigDecimal total = BigDecimal.ZERO;
+        for (Item item : items) {
+            total = total.add(item.getPrice().multiply(BigDecimal.valueOf(item.getQuantity())));
+        }
+        return total;
+    }
+
+    public List<String> lowStock(int threshold) {
+        return items.stream()
+                .filter(i -> i.getQuantity() < threshold)
+                .map(Item::getSku)
+                .sorted()
+                .collect(Collectors.toList());
+    }
+
+    public List<Item> getItems() {
+        return Collections.unmodifiableList(items);
+    }
+
+    @Override
+    public String toString() {
+        return String.format("Inventory[%s, %d items]", warehouse, items.size());
+    }
+
+    public static class Item implements Serializable, Comparable<Item> {
+        private final String sku;
+        private final BigDecimal price;
+        private int quantity;
+
+        public Item(String sku, BigDecimal price, int quantity) {
+            this.sku = sku;
+            this.price = price;
+            this.quantity = quantity;
+        }
+
+        public String getSku() { return sku; }
+        public BigDecimal getPrice() { return price; }
+        public int getQuantity() { return quantity; }
+
+        @Override
+        public int compareTo(Item other) {
+            return sku.compareTo(other.sku);
+        }
+    }
+
+    public static class InventoryFullException extends Exception {
+        public InventoryFullException(String message) {
+            super(message);
+        }
+    }
+
+    public static void main(String[] args) throws Exception {
+        Inventory inv = new Inventory("north");
+        inv.add(new Item("A-1", new BigDecimal("9.99"), 3));
+        inv.add(new Item("B-2", new BigDecimal("0.50"), 120));
+        char c = 'x';
+        double d = 1.5e3;
+        System.out.println(inv + " worth " + inv.totalValue() + c + d);
+    }
+}
@@ -0,0 +1,70 @@
+'use strict';
+
+const http = require('http');
+const { URL } = require('url');
+
+const routes = new Map();
+
+function route(method, path, handler) {
+  routes.set(`${method} ${path}`, handler);
+}
+
+function json(res, status, body) {
+  const data = JSON.stringify(body);
+  res.writeHead(status, {
+    'Content-Type': 'application/json; charset=utf-8',
+    'Content-Length': Buffer.byteLength(data),
+  });
+  res.end(data);
+}
+
+async function readBody(req, limit = 1 << 20) {
+  let size = 0;
+  const chunks = [];
+  for await (const chunk of req) {
+    size += chunk.length;
+    if (size > limit) {
+      throw Object.assign(new Error('body too large'), { status: 413 });
+    }
+    chunks.push(chunk);
+  }
+  return Buffer.concat(chunks).toString('utf8');
+}
+
+const users = [
+  { id: 1, name: 'Ada', roles: ['admin'] },
+  { id: 2, name: 'Grace', roles: [] },
+];
+
+route('GET', '/users', (req, res, url) => {
+  const q = (url.searchParams.get('q') || '').toLowerCase();
+  json(res, 200, users.filter((u) => u.name.toLowerCase().includes(q)));
+});
+
+route('POST', '/users', async (req, res) => {
+  const body = JSON.parse(await readBody(req));
+  if (typeof body.name !== 'string' || !/^[\w ]{1,64}$/.test(body.name)) {
+    return json(res, 400, { error: 'invalid name' });
+  }
+  const user = { id: users.length + 1, name: body.name, roles: body.roles ?? [] };
+  users.push(user);
+  json(res, 201, user);
+});
+
+const server = http.createServer(async (req, res) => {
+  const url = new URL(req.url, `http://${req.headers.host}`);
+  const handler = routes.get(`${req.method} ${url.pathname}`);
+  if (!handler) {
+    return json(res, 404, { error: 'not found' });
+  }
+  try {
+    await handler(req, res, url);
+  } catch (err) {
+    console.error(err);
+    json(res, err.status || 500, { error: err.message });
+  }
+});
+
+server.listen(process.env.PORT || 8080, () => {
+  console.log('listening on %d', server.address().port);
+});
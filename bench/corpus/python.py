@@ -0,0 +1,86 @@
+#!/usr/bin/env python3
+"""Summarize access logs by status code and path."""
+
+import argparse
+import collections
+import re
+import sys
+from dataclasses import dataclass, field
+from typing import Dict, Iterable, Iterator, List, Optional
+
+LINE_RE = re.compile(
+    r'(?P<ip>\S+) \S+ \S+ \[(?P<time>[^\]]+)\] '
+    r'"(?P<method>[A-Z]+) (?P<path>\S+) HTTP/[\d.]+" (?P<status>\d{3}) (?P<size>\d+|-)'
+)
+
+
+@dataclass
+class Summary:
+    total: int = 0
+    bytes: int = 0
+    by_status: Dict[int, int] = field(default_factory=collections.Counter)
+    by_path: Dict[str, int] = field(default_factory=collections.Counter)
+
+    def add(self, status: int, path: str, size: Optional[int]) -> None:
+        self.total += 1
+        self.bytes += size or 0
+        self.by_status[status] += 1
+        self.by_path[path.split("?", 1)[0]] += 1
+
+    def top_paths(self, n: int = 10) -> List[tuple]:
+        return sorted(self.by_path.items(), key=lambda kv: (-kv[1], kv[0]))[:n]
+
+
+def parse(lines: Iterable[str]) -> Iterator[dict]:
+    for lineno, line in enumerate(lines, start=1):
+        match = LINE_RE.match(line)
+        if match is None:
+            print(f"warning: line {lineno} did not match", file=sys.stderr)
+            continue
+        yield match.groupdict()
+
+
+def summarize(lines: Iterable[str]) -> Summary:
+    summary = Summary()
+    for record in parse(lines):
+        size = None if record["size"] == "-" else int(record["size"])
+        summary.add(int(record["status"]), record["path"], size)
+    return summary
+
+
+class Report:
+    """Formats a summary as text."""
+
+    def __init__(self, summary: Summary, top: int = 5):
+        self.summary = summary
+        self.top = top
+
+    def __str__(self) -> str:
+        out = [f"{self.summary.total} requests, {self.summary.bytes / 1024:.1f} KiB"]
+        for status, count in sorted(self.summary.by_status.items()):
+            out.append(f"  {status}: {count:>6} ({count / max(self.summary.total, 1):.0%})")
+        out.append("top paths:")
+        out.extend(f"  {count:>6} {path}" for path, count in self.summary.top_paths(self.top))
+        return "\n".join(out)
+
+
+def main(argv=None) -> int:
+    parser = argparse.ArgumentParser(description=__doc__)
+    parser.add_argument("files", nargs="*", type=argparse.FileType("r"), default=[sys.stdin])
+    parser.add_argument("--top", type=int, default=5)
+    args = parser.parse_args(argv)
+
+    summary = Summary()
+    for f in args.files:
+        with f:
+            partial = summarize(f)
+            summary.total += partial.total
+            summary.bytes += partial.bytes
+            summary.by_status.update(partial.by_status)
+            summary.by_path.update(partial.by_path)
+    print(Report(summary, top=args.top))
+    return 0 if summary.total else 1
+
+
+if __name__ == "__main__":
+    sys.exit(main())
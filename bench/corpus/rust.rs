@@ -0,0 +1,140 @@
+//! A tiny expression evaluator.
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Num(f64),
+    Ident(String),
+    Op(char),
+    LParen,
+    RParen,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    UnexpectedChar(char, usize),
+    UnexpectedEnd,
+    UnknownVariable(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnexpectedChar(c, i) => write!(f, "unexpected {:?} at {}", c, i),
+            Error::UnexpectedEnd => f.write_str("unexpected end of input"),
+            Error::UnknownVariable(name) => write!(f, "unknown variable `{}`", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        match c {
+            ' ' | '\t' | '\n' => {
+                chars.next();
+            }
+            '0'..='9' | '.' => {
+                let mut end = i;
+                while let Some(&(j, d)) = chars.peek() {
+                    if !(d.is_ascii_digit() || d == '.') {
+                        break;
+                    }
+                    end = j + d.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Num(input[i..end].parse().unwrap()));
+            }
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let mut name = String::new();
+                while let Some(&(_, d)) = chars.peek() {
+                    if !(d.is_alphanumeric() || d == '_') {
+                        break;
+                    }
+                    name.push(d);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(name));
+            }
+            '+' | '-' | '*' | '/' => {
+                tokens.push(Token::Op(c));
+                chars.next();
+            }
+            '(' => {
+                tokens.push(Token::LParen);
+                chars.next();
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                chars.next();
+            }
+            _ => return Err(Error::UnexpectedChar(c, i)),
+        }
+    }
+    Ok(tokens)
+}
+
+pub struct Evaluator<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    vars: &'a HashMap<String, f64>,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(tokens: &'a [Token], vars: &'a HashMap<String, f64>) -> Self {
+        Evaluator { tokens, pos: 0, vars }
+    }
+
+    pub fn expr(&mut self) -> Result<f64, Error> {
+        let mut lhs = self.term()?;
+        while let Some(Token::Op(op @ '+')) | Some(Token::Op(op @ '-')) = self.tokens.get(self.pos) {
+            self.pos += 1;
+            let rhs = self.term()?;
+            lhs = if *op == '+' { lhs + rhs } else { lhs - rhs };
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<f64, Error> {
+        let mut lhs = self.atom()?;
+        while let Some(Token::Op(op @ '*')) | Some(Token::Op(op @ '/')) = self.tokens.get(self.pos) {
+            self.pos += 1;
+            let rhs = self.atom()?;
+            lhs = if *op == '*' { lhs * rhs } else { lhs / rhs };
+        }
+        Ok(lhs)
+    }
+
+    fn atom(&mut self) -> Result<f64, Error> {
+        let token = self.tokens.get(self.pos).ok_or(Error::UnexpectedEnd)?;
+        self.pos += 1;
+        match token {
+            Token::Num(n) => Ok(*n),
+            Token::Ident(name) => self
+                .vars
+                .get(name)
+                .copied()
+                .ok_or_else(|| Error::UnknownVariable(name.clone())),
+            Token::Op('-') => Ok(-self.atom()?),
+            Token::LParen => {
+                let v = self.expr()?;
+                self.pos += 1; // skip ')'
+                Ok(v)
+            }
+            _ => Err(Error::UnexpectedEnd),
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut vars = HashMap::new();
+    vars.insert("pi".to_string(), std::f64::consts::PI);
+    let tokens = tokenize("2 * (pi + 1.5) / 4")?;
+    println!("{}", Evaluator::new(&tokens, &vars).expr()?);
+    Ok(())
+}
@@ -0,0 +1,47 @@
+#!/usr/bin/env bash
+# Back up a directory to a timestamped, compressed archive.
+set -euo pipefail
+
+readonly DEST="${BACKUP_DEST:-/var/backups}"
+readonly KEEP=${KEEP:-7}
+
+usage() {
+  echo "usage: $(basename "$0") [-n] DIR..." >&2
+  exit 2
+}
+
+dry_run=false
+while getopts ":n" opt; do
+  case "$opt" in
+    n) dry_run=true ;;
+    *) usage ;;
+  esac
+done
+shift $((OPTIND - 1))
+[[ $# -gt 0 ]] || usage
+
+mkdir -p "$DEST"
+for dir in "$@"; do
+  if [[ ! -d "$dir" ]]; then
+    echo "skipping $dir: not a directory" >&2
+    continue
+  fi
+  name="$(basename "$dir")-$(date +%Y%m%d-%H%M%S).tar.gz"
+  if $dry_run; then
+    echo "would write $DEST/$name"
+  else
+    tar -czf "$DEST/$name" -C "$(dirname "$dir")" "$(basename "$dir")"
+    echo "wrote $DEST/$name ($(du -h "$DEST/$name" | cut -f1))"
+  fi
+
+  # Only keep the newest $KEEP archives per directory.
+  mapfile -t old < <(ls -1t "$DEST/$(basename "$dir")"-*.tar.gz 2>/dev/null | tail -n +$((KEEP + 1)))
+  for f in "${old[@]}"; do
+    $dry_run && echo "would remove $f" || rm -f -- "$f"
+  done
+done
+
+cat <<EOT
+Backups in $DEST:
+$(ls -1 "$DEST" | wc -l) files
+EOT
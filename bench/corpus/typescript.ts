@@ -0,0 +1,86 @@
+import { EventEmitter } from "events";
+
+export type Listener<T> = (value: T, previous: T | undefined) => void;
+
+export interface StoreOptions<T> {
+  initial: T;
+  equals?: (a: T, b: T) => boolean;
+  name?: string;
+}
+
+enum Status {
+  Idle = "idle",
+  Loading = "loading",
+  Error = "error",
+}
+
+/**
+ * A minimal observable store.
+ */
+export class Store<T extends object> extends EventEmitter {
+  private value: T;
+  private readonly equals: (a: T, b: T) => boolean;
+  public status: Status = Status.Idle;
+
+  constructor(private readonly options: StoreOptions<T>) {
+    super();
+    this.value = options.initial;
+    this.equals = options.equals ?? ((a, b) => a === b);
+  }
+
+  get(): Readonly<T> {
+    return this.value;
+  }
+
+  set(next: T): void {
+    const previous = this.value;
+    if (this.equals(previous, next)) {
+      return;
+    }
+    this.value = next;
+    this.emit("change", next, previous);
+  }
+
+  update(fn: (value: T) => Partial<T>): void {
+    this.set({ ...this.value, ...fn(this.value) });
+  }
+
+  subscribe(listener: Listener<T>): () => void {
+    this.on("change", listener);
+    listener(this.value, undefined);
+    return () => this.off("change", listener);
+  }
+
+  async load(url: string, signal?: AbortSignal): Promise<void> {
+    this.status = Status.Loading;
+    try {
+      const response = await fetch(url, { signal });
+      if (!response.ok) {
+        throw new Error(`failed to load ${this.options.name ?? "store"}: ${response.status}`);
+      }
+      this.set((await response.json()) as T);
+      this.status = Status.Idle;
+    } catch (err: unknown) {
+      this.status = Status.Error;
+      console.error(err instanceof Error ? err.message : String(err));
+    }
+  }
+}
+
+interface Todo {
+  id: number;
+  title: string;
+  done: boolean;
+}
+
+const todos = new Store<{ items: Todo[] }>({ initial: { items: [] }, name: "todos" });
+const unsubscribe = todos.subscribe(({ items }) => {
+  const remaining = items.filter((t) => !t.done).length;
+  console.log(`${remaining}/${items.length} remaining`);
+});
+
+todos.update(({ items }) => ({ items: [...items, { id: 1, title: "write <tests> & docs", done: false }] }));
+todos.update(({ items }) => ({ items: items.map((t) => (t.id === 1 ? { ...t, done: true } : t)) }));
+unsubscribe();
+
+export default Store;
@@ -0,0 +1,29 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// main passes the git revision of syntect we are built with to the server as
+// SYNTECT_REVISION, which is reported by `/ready`. Cargo resolves the
+// dependencies, and so writes Cargo.lock, before running build scripts.
+fn main() {
+    let lock = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+    if let Some(rev) = fs::read_to_string(&lock)
+        .ok()
+        .and_then(|lock| syntect_revision(&lock))
+    {
+        println!("cargo:rustc-env=SYNTECT_REVISION={}", rev);
+    }
+}
+
+// syntect_revision returns the git revision of the syntect package in the
+// given Cargo.lock.
+fn syntect_revision(lock: &str) -> Option<String> {
+    let mut lines = lock.lines();
+    lines.find(|line| *line == "name = \"syntect\"")?;
+    lines
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| line.strip_prefix("source = \""))
+        .and_then(|source| source.split('#').nth(1))
+        .map(|rev| rev.trim_end_matches('"').to_string())
+}
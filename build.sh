@@ -1,2 +1,2 @@
 set -ex
-docker build -t sourcegraph/syntect_server .
+docker build -t sourcegraph/syntect_server --build-arg VERSION=$(git rev-parse --short HEAD) .
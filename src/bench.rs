@@ -0,0 +1,323 @@
+use crate::{cache, css_table};
+use crate::{highlight, Query, SYNTAX_SET};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+use syntect::{
+    html::ClassStyle,
+    parsing::{BasicScopeStackOp, ParseState, Scope, ScopeStack},
+    util::LinesWithEndings,
+};
+
+// The corpus checked into the repository, relative to its root, which the
+// benchmark is usually run from.
+const DEFAULT_CORPUS: &str = "bench/corpus";
+
+// The theme used to benchmark themed (non-CSS) mode.
+const THEME: &str = "Solarized (dark)";
+
+const STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+const USAGE: &str = "usage:
+    syntect_server bench [--corpus DIR] [--threads N] [--iterations N] [--json]
+    syntect_server bench compare BASELINE.json NEW.json [--max-regression PERCENT]";
+
+#[derive(Serialize, Deserialize)]
+struct Report {
+    threads: usize,
+    iterations: usize,
+    results: Vec<Measurement>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Measurement {
+    language: String,
+    mode: String,
+    lines: usize,
+    seconds: f64,
+    lines_per_sec: f64,
+}
+
+/// run implements the `bench` subcommand.
+///
+/// It highlights every file in the corpus directory (one representative file
+/// per language, named after the language) with `highlight()` in both CSS and
+/// themed mode, on N threads concurrently as the Rocket server threads would,
+/// and reports the throughput per language in lines/sec. With `--json` the
+/// report is printed as JSON instead, and two such reports can be compared
+/// with `bench compare` to catch performance regressions, e.g. from a syntect
+/// update or a change to `css_table.rs`.
+pub fn run(args: &[String]) -> i32 {
+    if args.first().map(String::as_str) == Some("compare") {
+        return compare(&args[1..]);
+    }
+
+    let mut corpus = DEFAULT_CORPUS.to_string();
+    let mut threads = 4;
+    let mut iterations = 20;
+    let mut json = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let ok = match arg.as_str() {
+            "--json" => {
+                json = true;
+                true
+            }
+            "--corpus" => args.next().map(|v| corpus = v.clone()).is_some(),
+            "--threads" => parse_next(&mut args, &mut threads),
+            "--iterations" => parse_next(&mut args, &mut iterations),
+            _ => false,
+        };
+        if !ok {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    }
+
+    // Identical inputs would otherwise be served from the result cache after
+    // the first iteration.
+    cache::disable();
+
+    let files = match corpus_files(Path::new(&corpus)) {
+        Ok(files) => files,
+        Err(err) => {
+            eprintln!("reading corpus {}: {}", corpus, err);
+            return 1;
+        }
+    };
+
+    let mut results = Vec::new();
+    for (language, filepath, code) in files {
+        for &css in &[true, false] {
+            let lines = code.lines().count() * iterations * threads;
+            let (filepath, code) = (filepath.clone(), code.clone());
+            let elapsed = on_threads(threads, move || {
+                for _ in 0..iterations {
+                    let result = highlight(Query {
+                        filepath: filepath.clone(),
+                        code: code.clone(),
+                        css,
+                        theme: THEME.to_string(),
+                        ..Query::default()
+                    });
+                    assert!(result.get("data").is_some(), "{}: {:?}", filepath, *result);
+                }
+            });
+            let m = Measurement {
+                language: language.clone(),
+                mode: if css { "css" } else { "themed" }.to_string(),
+                lines,
+                seconds: elapsed.as_secs_f64(),
+                lines_per_sec: lines as f64 / elapsed.as_secs_f64(),
+            };
+            if !json {
+                report(&m.language, &m.mode, m.lines_per_sec, "lines/s");
+            }
+            results.push(m);
+        }
+    }
+
+    if json {
+        let report = Report {
+            threads,
+            iterations,
+            results,
+        };
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        bench_classes(threads, iterations);
+    }
+    0
+}
+
+fn parse_next<'a, T: std::str::FromStr>(
+    args: &mut impl Iterator<Item = &'a String>,
+    v: &mut T,
+) -> bool {
+    match args.next().and_then(|s| s.parse().ok()) {
+        Some(parsed) => {
+            *v = parsed;
+            true
+        }
+        None => false,
+    }
+}
+
+// corpus_files returns the (language, filepath, code) of every file in the
+// corpus, sorted by file name. The language is the file name without its
+// extension, e.g. "go" for "go.go" or "typescript" for "typescript.ts".
+fn corpus_files(dir: &Path) -> std::io::Result<Vec<(String, String, String)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let language = path.file_stem().unwrap().to_string_lossy().to_string();
+        files.push((language, file_name, fs::read_to_string(&path)?));
+    }
+    files.sort();
+    Ok(files)
+}
+
+// compare compares two JSON reports, and fails if the throughput of any
+// language and mode dropped by more than the allowed percentage.
+fn compare(args: &[String]) -> i32 {
+    let (baseline, new, max_regression) = match args {
+        [baseline, new] => (baseline, new, 10.0),
+        [baseline, new, flag, percent] if flag == "--max-regression" => match percent.parse() {
+            Ok(percent) => (baseline, new, percent),
+            Err(_) => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let (baseline, new) = match (read_report(baseline), read_report(new)) {
+        (Ok(baseline), Ok(new)) => (baseline, new),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+
+    let before: HashMap<(&str, &str), f64> = baseline
+        .results
+        .iter()
+        .map(|m| ((m.language.as_str(), m.mode.as_str()), m.lines_per_sec))
+        .collect();
+    let mut regressed = false;
+    for m in &new.results {
+        let old = match before.get(&(m.language.as_str(), m.mode.as_str())) {
+            Some(&old) => old,
+            None => {
+                println!(
+                    "{:<12} {:<8} {:>14.0} lines/s (new)",
+                    m.language, m.mode, m.lines_per_sec
+                );
+                continue;
+            }
+        };
+        let change = (m.lines_per_sec - old) / old * 100.0;
+        let flag = if change < -max_regression {
+            regressed = true;
+            "  REGRESSION"
+        } else {
+            ""
+        };
+        println!(
+            "{:<12} {:<8} {:>14.0} -> {:>14.0} lines/s ({:+.1}%){}",
+            m.language, m.mode, old, m.lines_per_sec, change, flag
+        );
+    }
+    if regressed {
+        1
+    } else {
+        0
+    }
+}
+
+fn read_report(path: &str) -> Result<Report, String> {
+    let data = fs::read_to_string(path).map_err(|err| format!("reading {}: {}", path, err))?;
+    serde_json::from_str(&data).map_err(|err| format!("parsing {}: {}", path, err))
+}
+
+// bench_classes compares building the class strings of every scope in the Go
+// and Java test inputs under the global SCOPE_REPO lock against the
+// per-thread cache.
+fn bench_classes(threads: usize, iterations: usize) {
+    let inputs = &[
+        ("go", "package main\n"),
+        (
+            "java",
+            "package com.lwl.boot.model;\n\npublic class Item implements Serializable {}\n",
+        ),
+    ];
+    for &(extension, code) in inputs {
+        let scopes = scopes_in(extension, code);
+        let spans = scopes.len() * iterations * 1000 * threads;
+        let locked = scopes.clone();
+        let elapsed = on_threads(threads, move || {
+            for _ in 0..iterations * 1000 {
+                for scope in &locked {
+                    css_table::scope_to_classes(scope, "hl-");
+                }
+            }
+        });
+        report(
+            extension,
+            "classes-locked",
+            spans as f64 / elapsed.as_secs_f64(),
+            "spans/s",
+        );
+        let elapsed = on_threads(threads, move || {
+            for _ in 0..iterations * 1000 {
+                for scope in &scopes {
+                    css_table::with_classes_for_scope(scope, STYLE, |s| s.len());
+                }
+            }
+        });
+        report(
+            extension,
+            "classes-cached",
+            spans as f64 / elapsed.as_secs_f64(),
+            "spans/s",
+        );
+    }
+}
+
+// on_threads runs f on the given number of threads at once and returns the
+// wall-clock time until all of them finished. Each thread loads its syntax set
+// before the clock starts.
+fn on_threads<F: Fn() + Send + Sync + 'static>(threads: usize, f: F) -> Duration {
+    let f = Arc::new(f);
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let (f, barrier) = (f.clone(), barrier.clone());
+            thread::spawn(move || {
+                SYNTAX_SET.with(|_| ());
+                barrier.wait();
+                f();
+            })
+        })
+        .collect();
+    barrier.wait();
+    let start = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+// scopes_in returns the scope of every span opened while highlighting code.
+fn scopes_in(extension: &str, code: &str) -> Vec<Scope> {
+    SYNTAX_SET.with(|ss| {
+        let sr = ss.find_syntax_by_extension(extension).unwrap();
+        let mut parse_state = ParseState::new(sr);
+        let mut stack = ScopeStack::new();
+        let mut scopes = Vec::new();
+        for line in LinesWithEndings::from(code) {
+            for (_, op) in parse_state.parse_line(line, ss) {
+                stack.apply_with_hook(&op, |basic_op, _| {
+                    if let BasicScopeStackOp::Push(scope) = basic_op {
+                        scopes.push(scope);
+                    }
+                });
+            }
+        }
+        scopes
+    })
+}
+
+fn report(name: &str, what: &str, rate: f64, unit: &str) {
+    println!("{:<12} {:<16} {:>14.0} {}", name, what, rate, unit);
+}
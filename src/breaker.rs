@@ -0,0 +1,219 @@
+use crate::config::env_or;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    // A grammar which keeps panicking or hanging on some input hurts every
+    // request for its language, so once it failed BREAKER_THRESHOLD times
+    // within BREAKER_WINDOW_SECS, its language is served as plain text for
+    // BREAKER_COOLDOWN_SECS. Setting BREAKER_THRESHOLD=0 disables breakers.
+    static ref BREAKERS: Mutex<Breakers> = Mutex::new(Breakers::from_env());
+
+    // Requests taking longer than this count as timeouts. Requests which the
+    // supervisor gives up on after WORKER_TIMEOUT_SECS count as timeouts too,
+    // but only in the supervisor's breakers, as the worker is killed along
+    // with its own.
+    static ref SLOW_REQUEST: Duration = Duration::from_millis(env_or("BREAKER_SLOW_MS", 5000));
+}
+
+/// Failure is a way in which highlighting code can fail.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    Panic,
+    Timeout,
+    /// The worker process died while highlighting, see supervisor::run.
+    Crash,
+}
+
+impl Failure {
+    fn as_str(self) -> &'static str {
+        match self {
+            Failure::Panic => "panic",
+            Failure::Timeout => "timeout",
+            Failure::Crash => "crash",
+        }
+    }
+}
+
+/// Breaker tracks the recent failures of a single syntax.
+#[derive(Default)]
+struct Breaker {
+    failures: VecDeque<Instant>,
+    last_failure: Option<Failure>,
+    tripped_until: Option<Instant>,
+}
+
+/// Breakers are the breakers of all syntaxes. Each server process has its
+/// own, and the supervisor has breakers shared by all of its workers.
+pub struct Breakers {
+    threshold: usize,
+    window: Duration,
+    cooldown: Duration,
+    syntaxes: HashMap<String, Breaker>,
+}
+
+impl Breakers {
+    /// from_env returns breakers configured by BREAKER_THRESHOLD,
+    /// BREAKER_WINDOW_SECS and BREAKER_COOLDOWN_SECS.
+    pub fn from_env() -> Self {
+        Breakers::new(
+            env_or("BREAKER_THRESHOLD", 5),
+            Duration::from_secs(env_or("BREAKER_WINDOW_SECS", 60)),
+            Duration::from_secs(env_or("BREAKER_COOLDOWN_SECS", 300)),
+        )
+    }
+
+    pub fn new(threshold: usize, window: Duration, cooldown: Duration) -> Self {
+        Breakers {
+            threshold,
+            window,
+            cooldown,
+            syntaxes: HashMap::new(),
+        }
+    }
+
+    /// record records a failure of the syntax, tripping its breaker once it
+    /// failed often enough.
+    pub fn record(&mut self, syntax: &str, failure: Failure, now: Instant) {
+        if self.threshold == 0 {
+            return;
+        }
+        let breaker = self.syntaxes.entry(syntax.to_string()).or_default();
+        while let Some(&at) = breaker.failures.front() {
+            if now.duration_since(at) <= self.window {
+                break;
+            }
+            breaker.failures.pop_front();
+        }
+        breaker.failures.push_back(now);
+        breaker.last_failure = Some(failure);
+        if breaker.failures.len() >= self.threshold {
+            eprintln!(
+                "circuit breaker tripped for {}: {} failures within {}s, last one a {}",
+                syntax,
+                breaker.failures.len(),
+                self.window.as_secs(),
+                failure.as_str()
+            );
+            breaker.tripped_until = Some(now + self.cooldown);
+            breaker.failures.clear();
+        }
+    }
+
+    /// reason returns why the breaker of the syntax is tripped, if it is.
+    pub fn reason(&self, syntax: &str, now: Instant) -> Option<String> {
+        let breaker = self.syntaxes.get(syntax)?;
+        match breaker.tripped_until {
+            Some(until) if until > now => Some(format!(
+                "highlighting {} is disabled for {}s after repeated failures (last: {})",
+                syntax,
+                (until - now).as_secs(),
+                breaker.last_failure.map_or("unknown", Failure::as_str)
+            )),
+            _ => None,
+        }
+    }
+
+    /// reasons returns the reasons of all breakers tripped at the time.
+    pub fn reasons(&self, now: Instant) -> Vec<String> {
+        let mut reasons: Vec<String> = self
+            .syntaxes
+            .keys()
+            .filter_map(|syntax| self.reason(syntax, now))
+            .collect();
+        reasons.sort();
+        reasons
+    }
+}
+
+/// tripped returns why the breaker of the syntax is tripped, if it is. Code in
+/// such a syntax must be highlighted as plain text instead.
+pub fn tripped(syntax: &str) -> Option<String> {
+    BREAKERS.lock().unwrap().reason(syntax, Instant::now())
+}
+
+/// tripped_reasons returns the reasons of all currently tripped breakers.
+pub fn tripped_reasons() -> Vec<String> {
+    BREAKERS.lock().unwrap().reasons(Instant::now())
+}
+
+/// is_slow returns whether highlighting which took as long as elapsed counts
+/// as a timeout.
+pub fn is_slow(elapsed: Duration) -> bool {
+    elapsed > *SLOW_REQUEST
+}
+
+/// Guard records a failure of the syntax if it is dropped while panicking, or
+/// after highlighting took longer than BREAKER_SLOW_MS.
+pub struct Guard<'a> {
+    syntax: &'a str,
+    start: Instant,
+}
+
+impl<'a> Guard<'a> {
+    pub fn new(syntax: &'a str) -> Self {
+        Guard {
+            syntax,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl<'a> Drop for Guard<'a> {
+    fn drop(&mut self) {
+        let failure = if thread::panicking() {
+            Failure::Panic
+        } else if is_slow(self.start.elapsed()) {
+            Failure::Timeout
+        } else {
+            return;
+        };
+        // The lock may be poisoned if we panicked while holding it.
+        if let Ok(mut breakers) = BREAKERS.lock() {
+            breakers.record(self.syntax, failure, Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Breakers, Failure};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn trips_after_threshold_within_window() {
+        let mut breakers = Breakers::new(3, Duration::from_secs(60), Duration::from_secs(300));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        breakers.record("Markdown", Failure::Panic, at(0));
+        breakers.record("Markdown", Failure::Timeout, at(10));
+        assert_eq!(breakers.reason("Markdown", at(10)), None);
+
+        // The first failure is outside of the window by now.
+        breakers.record("Markdown", Failure::Panic, at(65));
+        assert_eq!(breakers.reason("Markdown", at(65)), None);
+
+        breakers.record("Markdown", Failure::Panic, at(66));
+        let reason = breakers.reason("Markdown", at(66)).unwrap();
+        assert!(reason.contains("Markdown"), "{}", reason);
+        assert!(reason.contains("panic"), "{}", reason);
+        assert_eq!(breakers.reason("Perl", at(66)), None);
+
+        // Cool-down is over.
+        assert!(breakers.reason("Markdown", at(365)).is_some());
+        assert_eq!(breakers.reason("Markdown", at(366)), None);
+    }
+
+    #[test]
+    fn disabled_with_zero_threshold() {
+        let mut breakers = Breakers::new(0, Duration::from_secs(60), Duration::from_secs(300));
+        let now = Instant::now();
+        for _ in 0..10 {
+            breakers.record("Markdown", Failure::Panic, now);
+        }
+        assert_eq!(breakers.reason("Markdown", now), None);
+    }
+}
@@ -0,0 +1,336 @@
+use crate::config::env_or;
+use rocket_contrib::json::JsonValue;
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+lazy_static! {
+    // The same popular files (READMEs, package.json, etc.) are highlighted
+    // over and over again with identical input, so we keep the most recently
+    // used results around. Setting CACHE_MAX_ENTRIES=0 disables the cache.
+    static ref RESULT_CACHE: Mutex<ResultCache> = Mutex::new(ResultCache::new(
+        env_or("CACHE_MAX_ENTRIES", 1000),
+        env_or("CACHE_MAX_BYTES", 64 * 1024 * 1024),
+    ));
+}
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Key identifies a highlighting result by everything that can influence the
+/// output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Key<'a> {
+    code: &'a str,
+    // The other inputs, joined into a string.
+    options: String,
+}
+
+impl<'a> Key<'a> {
+    pub fn new(
+        code: &'a str,
+        syntax: &str,
+        is_plaintext: bool,
+        theme: &str,
+        mode: &str,
+        line_length_limit: Option<usize>,
+    ) -> Self {
+        Key {
+            code,
+            options: format!(
+                "{}\0{}\0{}\0{}\0{:?}",
+                syntax, is_plaintext, theme, mode, line_length_limit
+            ),
+        }
+    }
+
+    fn hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.code.hash(&mut hasher);
+        self.options.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+struct Entry {
+    // The code and options of the key are kept to compare them on lookup, as
+    // different keys may have the same hash.
+    code: String,
+    options: String,
+    value: JsonValue,
+    size: usize,
+    tick: u64,
+}
+
+/// ResultCache is a least-recently-used cache of highlighting results, bounded
+/// both by the number of entries and by the total size of the cached data.
+pub struct ResultCache {
+    max_entries: usize,
+    max_bytes: usize,
+    bytes: usize,
+    tick: u64,
+    // Maps the hashes of keys to their entries.
+    entries: HashMap<u64, Entry>,
+    // Maps the tick at which an entry was last used to its hash, so that the
+    // least recently used entry is always the first one.
+    lru: BTreeMap<u64, u64>,
+}
+
+impl ResultCache {
+    pub fn new(max_entries: usize, max_bytes: usize) -> Self {
+        ResultCache {
+            max_entries,
+            max_bytes,
+            bytes: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &Key) -> Option<JsonValue> {
+        self.tick += 1;
+        let hash = key.hash();
+        let entry = self.entries.get_mut(&hash)?;
+        if entry.code != key.code || entry.options != key.options {
+            return None;
+        }
+        self.lru.remove(&entry.tick);
+        entry.tick = self.tick;
+        self.lru.insert(self.tick, hash);
+        Some(entry.value.clone())
+    }
+
+    /// insert caches the value, whose size includes that of the key, see
+    /// entry_size. An entry for another key with the same hash is replaced.
+    pub fn insert(&mut self, key: &Key, value: JsonValue, size: usize) {
+        if self.max_entries == 0 || size > self.max_bytes {
+            return;
+        }
+        let hash = key.hash();
+        if let Some(old) = self.entries.remove(&hash) {
+            self.lru.remove(&old.tick);
+            self.bytes -= old.size;
+        }
+        while self.entries.len() >= self.max_entries || self.bytes + size > self.max_bytes {
+            if !self.evict_oldest() {
+                break;
+            }
+        }
+        self.tick += 1;
+        self.lru.insert(self.tick, hash);
+        self.bytes += size;
+        self.entries.insert(
+            hash,
+            Entry {
+                code: key.code.to_string(),
+                options: key.options.clone(),
+                value,
+                size,
+                tick: self.tick,
+            },
+        );
+    }
+
+    fn evict_oldest(&mut self) -> bool {
+        let tick = match self.lru.keys().next() {
+            Some(&tick) => tick,
+            None => return false,
+        };
+        let hash = self.lru.remove(&tick).unwrap();
+        if let Some(entry) = self.entries.remove(&hash) {
+            self.bytes -= entry.size;
+        }
+        true
+    }
+}
+
+/// get returns the cached result for key, if any, and counts the hit or miss.
+pub fn get(key: &Key) -> Option<JsonValue> {
+    let result = RESULT_CACHE.lock().unwrap().get(key);
+    match result {
+        Some(_) => HITS.fetch_add(1, Ordering::Relaxed),
+        None => MISSES.fetch_add(1, Ordering::Relaxed),
+    };
+    result
+}
+
+/// insert caches a successful result.
+pub fn insert(key: Key, value: &JsonValue) {
+    let size = entry_size(&key, value);
+    RESULT_CACHE
+        .lock()
+        .unwrap()
+        .insert(&key, value.clone(), size);
+}
+
+// entry_size returns the size of an entry for the key and value: the length
+// of its code and of the value serialized as JSON. A result's in-memory size
+// is not simply related to its serialized length, but grows with it, whether
+// its data is a string or e.g. an array of semantic tokens.
+fn entry_size(key: &Key, value: &Value) -> usize {
+    // Counter counts the bytes written to it, so that the value needn't be
+    // serialized into memory to measure it.
+    struct Counter(usize);
+    impl io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut counter = Counter(0);
+    let _ = serde_json::to_writer(&mut counter, value);
+    key.code.len() + key.options.len() + counter.0
+}
+
+/// disable empties the cache and keeps it from caching anything from now on,
+/// regardless of CACHE_MAX_ENTRIES.
+pub fn disable() {
+    *RESULT_CACHE.lock().unwrap() = ResultCache::new(0, 0);
+}
+
+pub fn stats() -> JsonValue {
+    let cache = RESULT_CACHE.lock().unwrap();
+    json!({
+        "hits": HITS.load(Ordering::Relaxed),
+        "misses": MISSES.load(Ordering::Relaxed),
+        "entries": cache.entries.len(),
+        "bytes": cache.bytes,
+        "max_entries": cache.max_entries,
+        "max_bytes": cache.max_bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entry_size, Key, ResultCache};
+    use crate::occurrences::{self, Format, Occurrence};
+
+    fn key(code: &str) -> Key {
+        Key::new(code, "Go", false, "", "css", None)
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ResultCache::new(2, 1024);
+        cache.insert(&key("a"), json!({"data": "a"}), 1);
+        cache.insert(&key("b"), json!({"data": "b"}), 1);
+        assert!(cache.get(&key("a")).is_some());
+        cache.insert(&key("c"), json!({"data": "c"}), 1);
+
+        assert!(cache.get(&key("a")).is_some());
+        assert!(cache.get(&key("b")).is_none());
+        assert!(cache.get(&key("c")).is_some());
+    }
+
+    #[test]
+    fn evicts_to_stay_under_byte_limit() {
+        let mut cache = ResultCache::new(10, 10);
+        cache.insert(&key("a"), json!({"data": "a"}), 6);
+        cache.insert(&key("b"), json!({"data": "b"}), 6);
+        assert!(cache.get(&key("a")).is_none());
+        assert!(cache.get(&key("b")).is_some());
+        assert_eq!(cache.bytes, 6);
+
+        // Results which can never fit are not cached at all.
+        cache.insert(&key("c"), json!({"data": "c"}), 11);
+        assert!(cache.get(&key("c")).is_none());
+        assert!(cache.get(&key("b")).is_some());
+    }
+
+    #[test]
+    fn key_depends_on_options() {
+        assert_ne!(
+            Key::new("x", "Go", false, "", "css", None),
+            Key::new("x", "Go", false, "", "themed", None)
+        );
+        assert_ne!(
+            Key::new("x", "Go", false, "", "css", None),
+            Key::new("x", "Rust", false, "", "css", None)
+        );
+        assert_ne!(
+            Key::new("x", "Go", false, "", "css", None),
+            Key::new("x", "Go", false, "", "css", Some(10))
+        );
+        assert_ne!(
+            Key::new("x", "Go", false, "", "css", None),
+            Key::new("x", "Go", true, "", "css", None)
+        );
+    }
+
+    #[test]
+    fn compares_code_on_hit() {
+        let mut cache = ResultCache::new(10, 1024);
+        cache.insert(&key("a"), json!({"data": "a"}), 1);
+        // Pretend that the code "b" has the same hash as "a".
+        cache.entries.values_mut().next().unwrap().code = "b".to_string();
+        assert!(cache.get(&key("a")).is_none());
+    }
+
+    #[test]
+    fn sizes_entries_by_serialized_length() {
+        let value = json!({"data": "<table></table>", "plaintext": false});
+        let size = entry_size(&key("code"), &value);
+        assert!(
+            size > "code".len() + r#"{"data":"<table></table>","plaintext":false}"#.len(),
+            "{}",
+            size
+        );
+    }
+
+    #[test]
+    fn evicts_semantic_tokens_to_stay_under_byte_limit() {
+        let entry = |code| {
+            let key = Key::new(code, "Go", false, "", "semantic_tokens Syntect", None);
+            let value = json!({"data": [0, 0, 7, 1, 0, 0, 8, 4, 2, 0], "plaintext": false});
+            let size = entry_size(&key, &value);
+            (key, value, size)
+        };
+        let (a, value, size) = entry("a");
+        assert!(size > 10 * "0,".len(), "{}", size);
+
+        let mut cache = ResultCache::new(10, 2 * size);
+        cache.insert(&a, value, size);
+        for &code in &["b", "c"] {
+            let (key, value, size) = entry(code);
+            cache.insert(&key, value, size);
+        }
+        assert!(cache.get(&a).is_none());
+        assert!(cache.get(&entry("b").0).is_some());
+        assert!(cache.get(&entry("c").0).is_some());
+        assert_eq!(cache.bytes, 2 * size);
+    }
+
+    #[test]
+    fn evicts_occurrences_to_stay_under_byte_limit() {
+        let occurrences: Vec<Occurrence> = (0..100)
+            .map(|line| Occurrence {
+                line,
+                start: 0,
+                len: 7,
+                kind: "Keyword",
+            })
+            .collect();
+        let value = json!({
+            "data": occurrences::serialize(Format::Json, "a.go", "Go", &occurrences),
+            "plaintext": false,
+        });
+        let key = |code| Key::new(code, "Go", false, "", "occurrences Json Syntect a.go", None);
+        let size = entry_size(&key("a"), &value);
+        let occurrence = r#"{"range":[0,0,7],"syntaxKind":"Keyword"}"#;
+        assert!(size > 100 * occurrence.len(), "{}", size);
+
+        let mut cache = ResultCache::new(10, size);
+        cache.insert(&key("a"), value.clone(), size);
+        cache.insert(&key("b"), value, size);
+        assert!(cache.get(&key("a")).is_none());
+        assert!(cache.get(&key("b")).is_some());
+    }
+}
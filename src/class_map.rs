@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use syntect::parsing::Scope;
+
+lazy_static! {
+    // The class maps which can be selected with the `classes` query field:
+    // the built-in ones, extended or overridden by CLASS_MAP_FILE.
+    static ref CLASS_MAPS: HashMap<String, ClassMap> = load(std::env::var("CLASS_MAP_FILE").ok());
+}
+
+// PYGMENTS maps scopes to the short token classes of Pygments stylesheets,
+// e.g. `.highlight .k`, see
+// https://github.com/pygments/pygments/blob/master/pygments/token.py
+const PYGMENTS: &[(&str, &str)] = &[
+    ("comment", "c"),
+    ("comment.line", "c1"),
+    ("comment.block", "cm"),
+    ("comment.block.documentation", "cs"),
+    ("constant", "no"),
+    ("constant.character.escape", "se"),
+    ("constant.language", "kc"),
+    ("constant.numeric", "m"),
+    ("constant.numeric.float", "mf"),
+    ("constant.numeric.integer", "mi"),
+    ("constant.numeric.integer.hexadecimal", "mh"),
+    ("constant.numeric.integer.octal", "mo"),
+    ("entity.name", "n"),
+    ("entity.name.class", "nc"),
+    ("entity.name.constant", "no"),
+    ("entity.name.function", "nf"),
+    ("entity.name.label", "nl"),
+    ("entity.name.namespace", "nn"),
+    ("entity.name.tag", "nt"),
+    ("entity.name.type", "nc"),
+    ("entity.other.attribute-name", "na"),
+    ("entity.other.inherited-class", "nc"),
+    ("invalid", "err"),
+    ("keyword", "k"),
+    ("keyword.control.import", "kn"),
+    ("keyword.declaration", "kd"),
+    ("keyword.operator", "o"),
+    ("keyword.operator.word", "ow"),
+    ("keyword.other.package", "kn"),
+    ("markup.bold", "gs"),
+    ("markup.deleted", "gd"),
+    ("markup.heading", "gh"),
+    ("markup.inserted", "gi"),
+    ("markup.italic", "ge"),
+    ("meta.annotation", "nd"),
+    ("meta.decorator", "nd"),
+    ("punctuation", "p"),
+    ("punctuation.definition.comment", ""),
+    ("punctuation.definition.string", ""),
+    ("storage", "k"),
+    ("storage.modifier", "kd"),
+    ("storage.type", "kt"),
+    ("string", "s"),
+    ("string.interpolated", "si"),
+    ("string.quoted.double", "s2"),
+    ("string.quoted.single", "s1"),
+    ("string.regexp", "sr"),
+    ("support.class", "nc"),
+    ("support.constant", "no"),
+    ("support.function", "nb"),
+    ("support.type", "kt"),
+    ("variable", "nv"),
+    ("variable.function", "nf"),
+    ("variable.language", "bp"),
+    ("variable.other", "n"),
+];
+
+// HLJS maps scopes to the classes of highlight.js stylesheets, e.g.
+// `.hljs-keyword`, see https://highlightjs.readthedocs.io/en/latest/css-classes-reference.html
+const HLJS: &[(&str, &str)] = &[
+    ("comment", "hljs-comment"),
+    ("constant", "hljs-variable constant_"),
+    ("constant.character.escape", "hljs-char escape_"),
+    ("constant.language", "hljs-literal"),
+    ("constant.numeric", "hljs-number"),
+    ("entity.name", "hljs-title"),
+    ("entity.name.class", "hljs-title class_"),
+    ("entity.name.function", "hljs-title function_"),
+    ("entity.name.section", "hljs-section"),
+    ("entity.name.tag", "hljs-name"),
+    ("entity.name.type", "hljs-title class_"),
+    ("entity.other.attribute-name", "hljs-attr"),
+    (
+        "entity.other.inherited-class",
+        "hljs-title class_ inherited__",
+    ),
+    ("keyword", "hljs-keyword"),
+    ("keyword.operator", "hljs-operator"),
+    ("markup.bold", "hljs-strong"),
+    ("markup.deleted", "hljs-deletion"),
+    ("markup.heading", "hljs-section"),
+    ("markup.inserted", "hljs-addition"),
+    ("markup.italic", "hljs-emphasis"),
+    ("markup.quote", "hljs-quote"),
+    ("markup.raw", "hljs-code"),
+    ("markup.underline.link", "hljs-link"),
+    ("meta.annotation", "hljs-meta"),
+    ("meta.decorator", "hljs-meta"),
+    ("punctuation", "hljs-punctuation"),
+    ("punctuation.definition.comment", ""),
+    ("punctuation.definition.string", ""),
+    ("storage", "hljs-keyword"),
+    ("storage.type", "hljs-type"),
+    ("string", "hljs-string"),
+    ("string.regexp", "hljs-regexp"),
+    ("support.class", "hljs-type"),
+    ("support.constant", "hljs-built_in"),
+    ("support.function", "hljs-built_in"),
+    ("support.type", "hljs-type"),
+    ("variable", "hljs-variable"),
+    ("variable.function", "hljs-title function_"),
+    ("variable.language", "hljs-variable language_"),
+    ("variable.other", ""),
+    ("variable.parameter", "hljs-params"),
+];
+
+/// ClassMap maps scopes to the classes of another highlighter's stylesheets.
+/// A scope gets the classes of the longest scope prefix in the map which
+/// matches it, and no span at all if there is none or its classes are empty.
+pub struct ClassMap {
+    name: String,
+    rules: Vec<(Scope, String)>,
+}
+
+impl ClassMap {
+    fn new(name: &str) -> Self {
+        ClassMap {
+            name: name.to_string(),
+            rules: Vec::new(),
+        }
+    }
+
+    /// from_rules returns a class map with the given pairs of scope prefix
+    /// and classes, which must be valid scopes.
+    pub fn from_rules(name: &str, rules: &[(&str, &str)]) -> Self {
+        let mut map = ClassMap::new(name);
+        for (prefix, classes) in rules {
+            map.set(prefix, classes).unwrap();
+        }
+        map
+    }
+
+    // set maps the scope prefix to classes, replacing any previous mapping.
+    fn set(&mut self, prefix: &str, classes: &str) -> Result<(), String> {
+        let scope = Scope::new(prefix).map_err(|err| format!("{}: {:?}", prefix, err))?;
+        self.rules.retain(|(s, _)| *s != scope);
+        self.rules.push((scope, classes.to_string()));
+        Ok(())
+    }
+
+    /// classes returns the classes for scope, if it gets any.
+    pub fn classes(&self, scope: Scope) -> Option<&str> {
+        self.rules
+            .iter()
+            .filter(|(prefix, _)| prefix.is_prefix_of(scope))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, classes)| classes.as_str())
+            .filter(|classes| !classes.is_empty())
+    }
+}
+
+// Class maps are identified by their name, and printed as such e.g. in
+// result cache keys.
+impl PartialEq for ClassMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for ClassMap {}
+
+impl Hash for ClassMap {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state)
+    }
+}
+
+impl fmt::Debug for ClassMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ClassMap({})", self.name)
+    }
+}
+
+/// get returns the class map with the given name.
+pub fn get(name: &str) -> Option<&'static ClassMap> {
+    CLASS_MAPS.get(name)
+}
+
+// load returns the built-in class maps, with the mappings of the given JSON
+// file applied on top of them. The file maps names of class maps to objects
+// mapping scope prefixes to classes, e.g.
+// `{"pygments": {"keyword.other.special": "kp"}}`.
+fn load(file: Option<String>) -> HashMap<String, ClassMap> {
+    let mut maps = HashMap::new();
+    for &(name, rules) in &[("pygments", PYGMENTS), ("hljs", HLJS)] {
+        maps.insert(name.to_string(), ClassMap::from_rules(name, rules));
+    }
+
+    if let Some(file) = file {
+        if let Err(err) = load_file(&file, &mut maps) {
+            eprintln!("CLASS_MAP_FILE {}: {}", file, err);
+        }
+    }
+    maps
+}
+
+fn load_file(file: &str, maps: &mut HashMap<String, ClassMap>) -> Result<(), String> {
+    let data = fs::read_to_string(file).map_err(|err| err.to_string())?;
+    let custom: HashMap<String, HashMap<String, String>> =
+        serde_json::from_str(&data).map_err(|err| err.to_string())?;
+    for (name, rules) in custom {
+        let map = maps
+            .entry(name.clone())
+            .or_insert_with(|| ClassMap::new(&name));
+        for (prefix, classes) in rules {
+            map.set(&prefix, &classes)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::load;
+    use std::env;
+    use std::fs;
+    use syntect::parsing::Scope;
+
+    fn scope(s: &str) -> Scope {
+        Scope::new(s).unwrap()
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let maps = load(None);
+        let pygments = &maps["pygments"];
+        assert_eq!(pygments.classes(scope("keyword.control.go")), Some("k"));
+        assert_eq!(
+            pygments.classes(scope("keyword.operator.word.python")),
+            Some("ow")
+        );
+        assert_eq!(
+            pygments.classes(scope("string.quoted.double.go")),
+            Some("s2")
+        );
+        assert_eq!(pygments.classes(scope("string.unquoted.yaml")), Some("s"));
+        assert_eq!(pygments.classes(scope("source.go")), None);
+        assert_eq!(pygments.classes(scope("meta.block.go")), None);
+        assert_eq!(
+            pygments.classes(scope("punctuation.definition.string.begin.go")),
+            None
+        );
+
+        let hljs = &maps["hljs"];
+        assert_eq!(
+            hljs.classes(scope("entity.name.function.go")),
+            Some("hljs-title function_")
+        );
+        assert_eq!(hljs.classes(scope("variable.other.go")), None);
+    }
+
+    #[test]
+    fn custom_mappings() {
+        let file = env::temp_dir().join(format!("class_map_test_{}.json", std::process::id()));
+        fs::write(
+            &file,
+            r#"{"pygments": {"keyword.other.special": "kp", "punctuation": ""}, "mine": {"string": "str"}}"#,
+        )
+        .unwrap();
+        let maps = load(Some(file.to_str().unwrap().to_string()));
+        fs::remove_file(&file).unwrap();
+
+        let pygments = &maps["pygments"];
+        assert_eq!(
+            pygments.classes(scope("keyword.other.special.c")),
+            Some("kp")
+        );
+        assert_eq!(pygments.classes(scope("keyword.other.c")), Some("k"));
+        assert_eq!(pygments.classes(scope("punctuation.terminator.c")), None);
+        assert_eq!(maps["mine"].classes(scope("string.quoted.c")), Some("str"));
+        assert_eq!(maps["mine"].classes(scope("keyword.c")), None);
+    }
+}
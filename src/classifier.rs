@@ -0,0 +1,358 @@
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+
+// Profile describes the code of a syntax which shares its file extensions with
+// other syntaxes, e.g. `.h` with C, C++ and Objective-C.
+struct Profile {
+    syntax: &'static str,
+    // Strings whose presence strongly suggests the syntax, like the heuristics
+    // of GitHub's linguist.
+    rules: &'static [&'static str],
+    // Words whose frequency suggests the syntax. Without any, the syntax is
+    // only picked if one of its rules matches, see needs_evidence.
+    keywords: &'static [&'static str],
+}
+
+const PROFILES: &[Profile] = &[
+    Profile {
+        syntax: "C",
+        rules: &[],
+        keywords: &[
+            "malloc", "free", "sizeof", "struct", "typedef", "NULL", "unsigned", "void",
+        ],
+    },
+    Profile {
+        syntax: "C++",
+        rules: &[
+            "std::",
+            "template<",
+            "template <",
+            "namespace ",
+            "public:",
+            "private:",
+            "#include <iostream>",
+            "#include <string>",
+            "#include <vector>",
+        ],
+        keywords: &[
+            "auto",
+            "class",
+            "constexpr",
+            "delete",
+            "new",
+            "nullptr",
+            "operator",
+            "override",
+            "typename",
+            "virtual",
+        ],
+    },
+    Profile {
+        syntax: "Objective-C",
+        rules: &[
+            "@interface",
+            "@implementation",
+            "@end",
+            "@property",
+            "@protocol",
+            "#import ",
+        ],
+        keywords: &["NO", "NSString", "YES", "id", "nil", "self"],
+    },
+    Profile {
+        syntax: "F Sharp",
+        rules: &["open System", "printfn", "|>", "let mutable", "module "],
+        keywords: &[
+            "fun", "let", "match", "member", "mutable", "rec", "type", "with",
+        ],
+    },
+    Profile {
+        syntax: "Forth",
+        rules: &[],
+        keywords: &[
+            "cr", "drop", "dup", "emit", "loop", "over", "rot", "swap", "then",
+        ],
+    },
+    Profile {
+        syntax: "GLSL",
+        rules: &["#version", "gl_Position", "gl_FragColor", "void main"],
+        keywords: &[
+            "mat4",
+            "precision",
+            "texture2D",
+            "uniform",
+            "varying",
+            "vec2",
+            "vec3",
+            "vec4",
+        ],
+    },
+    Profile {
+        syntax: "Coq",
+        rules: &[
+            "Require Import",
+            "Theorem ",
+            "Lemma ",
+            "Proof.",
+            "Qed.",
+            "Inductive ",
+            "Fixpoint ",
+        ],
+        keywords: &[
+            "apply",
+            "destruct",
+            "induction",
+            "intros",
+            "reflexivity",
+            "simpl",
+        ],
+    },
+    Profile {
+        syntax: "Perl",
+        rules: &["use strict", "use warnings", "my $", "=~"],
+        keywords: &["foreach", "my", "print", "sub", "unless", "use"],
+    },
+    Profile {
+        syntax: "COBOL",
+        rules: &[
+            "IDENTIFICATION DIVISION",
+            "PROCEDURE DIVISION",
+            "DATA DIVISION",
+            "WORKING-STORAGE",
+        ],
+        keywords: &["COMPUTE", "DISPLAY", "MOVE", "PERFORM", "PIC"],
+    },
+    Profile {
+        syntax: "Lisp",
+        rules: &["(define ", "(defun ", "(lambda "],
+        keywords: &["car", "cdr", "cond", "cons", "define", "lambda"],
+    },
+    Profile {
+        syntax: "JS Custom - React",
+        // JSX itself is no evidence, as plain JavaScript often has HTML in
+        // its strings. Without these, the code is plain JavaScript, i.e.
+        // JS Custom - Default.
+        rules: &["import React", "from 'react'", "from \"react\"", "React."],
+        keywords: &[],
+    },
+];
+
+// MIXES are syntaxes whose code mixes that of others, e.g. Objective-C++ that
+// of Objective-C and C++. Code matching rules of all of the others scores the
+// sum of their scores for the mix, so that the mix beats each of them, and
+// nothing otherwise, as their keywords overlap too much to tell.
+const MIXES: &[(&str, &[&str])] = &[("Objective-C++", &["Objective-C", "C++"])];
+
+// A matching rule counts as much as this many keywords.
+const RULE_WEIGHT: usize = 10;
+
+// Only the start of the code is classified, which is plenty to tell languages
+// apart and keeps huge files cheap.
+const MAX_BYTES: usize = 64 * 1024;
+
+/// classify returns the most likely syntax of the code among the syntaxes
+/// with the file extension, and the confidence in it between 0 and 1. Returns
+/// None unless several syntaxes have the extension.
+///
+/// Without evidence for any of them, the syntax `find_syntax_by_extension`
+/// picks is returned, with a confidence of one over the number of candidates,
+/// unless it is one which needs evidence, such as React's.
+pub fn classify<'a>(
+    ss: &'a SyntaxSet,
+    extension: &str,
+    code: &str,
+) -> Option<(&'a SyntaxReference, f64)> {
+    // Some syntaxes have an empty extension, which must not match.
+    if extension.is_empty() {
+        return None;
+    }
+    let default = ss.find_syntax_by_extension(extension)?;
+    let mut candidates: Vec<&SyntaxReference> = vec![default];
+    for syntax in ss.syntaxes() {
+        if syntax.name != default.name && syntax.file_extensions.iter().any(|e| e == extension) {
+            candidates.push(syntax);
+        }
+    }
+    if candidates.len() < 2 {
+        return None;
+    }
+
+    let mut end = code.len().min(MAX_BYTES);
+    while !code.is_char_boundary(end) {
+        end -= 1;
+    }
+    let code = &code[..end];
+    let words: Vec<&str> = code
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|w| !w.is_empty())
+        .collect();
+
+    let (candidates, scores): (Vec<&SyntaxReference>, Vec<usize>) = candidates
+        .into_iter()
+        .map(|syntax| (syntax, score(&syntax.name, code, &words)))
+        .filter(|&(syntax, score)| score > 0 || !needs_evidence(&syntax.name))
+        .unzip();
+    if candidates.is_empty() {
+        return Some((default, 0.0));
+    }
+    let total: usize = scores.iter().sum();
+    if total == 0 {
+        return Some((candidates[0], 1.0 / candidates.len() as f64));
+    }
+    // The first candidate with the best score wins, so the default one does
+    // on ties.
+    let (best, best_score) =
+        scores
+            .iter()
+            .enumerate()
+            .fold((0, 0), |(best, best_score), (i, &score)| {
+                if score > best_score {
+                    (i, score)
+                } else {
+                    (best, best_score)
+                }
+            });
+    Some((candidates[best], best_score as f64 / total as f64))
+}
+
+// score returns the evidence for the code being of the syntax.
+fn score(syntax: &str, code: &str, words: &[&str]) -> usize {
+    if let Some((_, syntaxes)) = MIXES.iter().find(|(mix, _)| *mix == syntax) {
+        let evidence: Vec<(usize, usize)> = syntaxes
+            .iter()
+            .map(|syntax| evidence(syntax, code, words))
+            .collect();
+        if evidence.iter().any(|&(rules, _)| rules == 0) {
+            return 0;
+        }
+        return evidence
+            .iter()
+            .map(|(rules, keywords)| rules * RULE_WEIGHT + keywords)
+            .sum();
+    }
+    let (rules, keywords) = evidence(syntax, code, words);
+    rules * RULE_WEIGHT + keywords
+}
+
+// needs_evidence returns whether the syntax can only be told by the rules of
+// its profile, as the code is of another syntax otherwise, e.g. React's or
+// Objective-C++'s.
+fn needs_evidence(syntax: &str) -> bool {
+    MIXES.iter().any(|(mix, _)| *mix == syntax)
+        || PROFILES
+            .iter()
+            .any(|p| p.syntax == syntax && p.keywords.is_empty())
+}
+
+// evidence returns the number of rules and keywords of the syntax's profile
+// which the code matches.
+fn evidence(syntax: &str, code: &str, words: &[&str]) -> (usize, usize) {
+    let profile = match PROFILES.iter().find(|p| p.syntax == syntax) {
+        Some(profile) => profile,
+        None => return (0, 0),
+    };
+    let rules = profile.rules.iter().filter(|r| code.contains(*r)).count();
+    let keywords = words
+        .iter()
+        .filter(|w| profile.keywords.contains(*w))
+        .count();
+    (rules, keywords)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::classify;
+    use crate::{highlight, Query};
+    use syntect::parsing::SyntaxSet;
+
+    #[test]
+    fn classifies_ambiguous_extensions() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let name = |extension, code| {
+            let (syntax, confidence) = classify(&ss, extension, code).unwrap();
+            assert!(confidence > 0.0 && confidence <= 1.0, "{}", confidence);
+            syntax.name.clone()
+        };
+        assert_eq!(
+            name(
+                "h",
+                "#include <vector>\nnamespace a {\nclass B : public C {};\n}\n"
+            ),
+            "C++"
+        );
+        assert_eq!(
+            name(
+                "h",
+                "#import <Foundation/Foundation.h>\n@interface A : NSObject\n@end\n"
+            ),
+            "Objective-C"
+        );
+        assert_eq!(
+            name(
+                "h",
+                "#import <Foundation/Foundation.h>\n#include <vector>\n@interface A : NSObject {\n  std::vector<int> v;\n}\n@end\n"
+            ),
+            "Objective-C++"
+        );
+        assert_eq!(
+            name("h", "typedef struct { unsigned n; } a;\nvoid *f(void);\n"),
+            "C"
+        );
+        assert_eq!(
+            name("fs", "open System\nlet f x = x |> printfn \"%d\"\n"),
+            "F Sharp"
+        );
+        assert_eq!(
+            name(
+                "fs",
+                "#version 330\nuniform vec4 c;\nvoid main() { gl_FragColor = c; }\n"
+            ),
+            "GLSL"
+        );
+        assert_eq!(
+            name("fs", ": square ( n -- n ) dup * ;\n5 square . cr\n"),
+            "Forth"
+        );
+        assert_eq!(
+            name(
+                "js",
+                "import React from 'react';\nexport const A = () => <div />;\n"
+            ),
+            "JS Custom - React"
+        );
+
+        // HTML in a string doesn't make JavaScript React.
+        assert_eq!(
+            name(
+                "js",
+                "const html = '<p>Hi</p><br/>';\ndocument.body.innerHTML = html;\n"
+            ),
+            "JS Custom - Default"
+        );
+
+        // Without evidence, the usual syntax for the extension is picked.
+        let (syntax, confidence) = classify(&ss, "fs", "\n").unwrap();
+        assert_eq!(syntax.name, ss.find_syntax_by_extension("fs").unwrap().name);
+        assert!(confidence < 0.5, "{}", confidence);
+
+        assert!(classify(&ss, "go", "package main\n").is_none());
+        assert!(classify(&ss, "", "").is_none());
+    }
+
+    #[test]
+    fn reports_confidence() {
+        let result = highlight(Query {
+            filepath: "a.h".to_string(),
+            code: "template <typename T> class A {};\n".to_string(),
+            css: true,
+            ..Query::default()
+        });
+        assert_eq!(result["confidence"], 1.0);
+        let result = highlight(Query {
+            filepath: "a.go".to_string(),
+            code: "package main\n".to_string(),
+            css: true,
+            ..Query::default()
+        });
+        assert!(result.get("confidence").is_none());
+    }
+}
@@ -0,0 +1,354 @@
+use crate::engine::{net_ops, ParsedLines};
+use syntect::parsing::{ParseState, Scope, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet};
+
+lazy_static! {
+    static ref SOURCE: Scope = Scope::new("source").unwrap();
+}
+
+/// Markup is a markup language whose code blocks name their language.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Markup {
+    /// ```` ```rust ```` or `~~~rust` fenced code blocks.
+    Markdown,
+    /// `.. code-block:: rust` directives, followed by an indented block.
+    RestructuredText,
+}
+
+impl Markup {
+    fn of(syntax: &SyntaxReference) -> Option<Self> {
+        match syntax.name.as_str() {
+            "Markdown" | "MultiMarkdown" => Some(Markup::Markdown),
+            "reStructuredText" => Some(Markup::RestructuredText),
+            _ => None,
+        }
+    }
+}
+
+/// Block is a code block, whose code is on lines start..end.
+#[derive(Debug, PartialEq)]
+struct Block {
+    // The line which names the language, e.g. the opening fence.
+    open: usize,
+    start: usize,
+    end: usize,
+    language: String,
+}
+
+/// has_code_blocks reports whether code of the syntax may contain code blocks
+/// to be highlighted with `highlight`.
+pub fn has_code_blocks(syntax: &SyntaxReference) -> bool {
+    Markup::of(syntax).is_some()
+}
+
+/// highlight replaces the ops of the lines of code blocks in markup with those
+/// of the syntax named by the block, found by the same extension and name
+/// lookups as the syntax of a file. Blocks which the markup's grammar already
+/// highlights, of unknown languages and lines which are not highlighted are
+/// left alone.
+pub fn highlight(
+    code: &str,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+    parsed_lines: &mut ParsedLines,
+) {
+    let markup = match Markup::of(syntax) {
+        Some(markup) => markup,
+        None => return,
+    };
+    let lines: Vec<&str> = syntect::util::LinesWithEndings::from(code).collect();
+    for block in blocks(markup, &lines) {
+        if let Some(syntax) = ss.find_syntax_by_token(&block.language) {
+            embed(&lines, parsed_lines, &block, ss, syntax);
+        }
+    }
+}
+
+// embed highlights the lines of the block with the syntax. The markup's own
+// ops for those lines are applied at the end of the block, after the scopes
+// of the embedded syntax are popped again, so that the lines after the block
+// start out with the same scope stack as before.
+fn embed(
+    lines: &[&str],
+    parsed_lines: &mut ParsedLines,
+    block: &Block,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+) {
+    let range = block.open..block.end.min(parsed_lines.len());
+    let embedded = parsed_lines[range]
+        .iter()
+        .flatten()
+        .flatten()
+        .any(|(_, op)| match op {
+            ScopeStackOp::Push(scope) => SOURCE.is_prefix_of(*scope),
+            _ => false,
+        });
+    if embedded {
+        return;
+    }
+
+    let mut parse_state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut markup_ops = Vec::new();
+    let mut last = None;
+    for i in block.start..block.end.min(parsed_lines.len()) {
+        let ops = match &mut parsed_lines[i] {
+            Some(ops) => ops,
+            None => continue,
+        };
+        markup_ops.append(ops);
+        *ops = parse_state.parse_line(lines[i], ss);
+        for (_, op) in ops.iter() {
+            stack.apply(op);
+        }
+        last = Some(i);
+    }
+
+    if let (Some(i), Some(ops)) = (last, last.and_then(|i| parsed_lines[i].as_mut())) {
+        let end = lines[i].len();
+        let depth = stack.as_slice().len();
+        if depth > 0 {
+            ops.push((end, ScopeStackOp::Pop(depth)));
+        }
+        ops.extend(net_ops(end, markup_ops));
+    }
+}
+
+// blocks returns the code blocks in the lines of markup which name a language.
+fn blocks(markup: Markup, lines: &[&str]) -> Vec<Block> {
+    match markup {
+        Markup::Markdown => markdown_blocks(lines),
+        Markup::RestructuredText => rst_blocks(lines),
+    }
+}
+
+// markdown_blocks returns the fenced code blocks. A fence is at least three
+// backticks or tildes, indented by up to three spaces, and a block is closed
+// by a fence of the same character which is at least as long, or the end of
+// the document.
+fn markdown_blocks(lines: &[&str]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let (fence, info) = match fence(lines[i]) {
+            Some(fence) => fence,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        let open = i;
+        i += 1;
+        while i < lines.len() {
+            match self::fence(lines[i]) {
+                Some((closing, info))
+                    if info.is_empty()
+                        && closing.starts_with(fence.chars().next().unwrap())
+                        && closing.len() >= fence.len() =>
+                {
+                    break
+                }
+                _ => i += 1,
+            }
+        }
+        // The language is the first word of the info string, e.g. `rust` in
+        // `rust,ignore` or `{.rust}`.
+        let language = info
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .next()
+            .unwrap_or("")
+            .trim_matches(|c| c == '{' || c == '}' || c == '.');
+        if !language.is_empty() {
+            blocks.push(Block {
+                open,
+                start: open + 1,
+                end: i,
+                language: language.to_string(),
+            });
+        }
+        // Skip the closing fence.
+        i += 1;
+    }
+    blocks
+}
+
+// fence returns the fence and info string of a fence line.
+fn fence(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let c = trimmed.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let len = trimmed.len() - trimmed.trim_start_matches(c).len();
+    if len < 3 {
+        return None;
+    }
+    let (fence, info) = trimmed.split_at(len);
+    let info = info.trim();
+    if c == '`' && info.contains('`') {
+        return None;
+    }
+    Some((fence, info))
+}
+
+// rst_blocks returns the code blocks of `code-block`, `code` and `sourcecode`
+// directives, which are the lines after the directive and its options which
+// are indented further than the directive.
+fn rst_blocks(lines: &[&str]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    for (open, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        let language = ["code-block::", "sourcecode::", "code::"]
+            .iter()
+            .find_map(|directive| {
+                trimmed
+                    .strip_prefix("..")?
+                    .trim_start()
+                    .strip_prefix(directive)
+            })
+            .map(str::trim);
+        let language = match language {
+            Some(language) if !language.is_empty() => language,
+            _ => continue,
+        };
+
+        let is_indented = |line: &str| {
+            let trimmed = line.trim_start();
+            trimmed.is_empty() || line.len() - trimmed.len() > indent
+        };
+        let mut start = open + 1;
+        while start < lines.len()
+            && is_indented(lines[start])
+            && (lines[start].trim().is_empty() || lines[start].trim_start().starts_with(':'))
+        {
+            start += 1;
+        }
+        let mut end = start;
+        while end < lines.len() && is_indented(lines[end]) {
+            end += 1;
+        }
+        while end > start && lines[end - 1].trim().is_empty() {
+            end -= 1;
+        }
+        if end > start {
+            blocks.push(Block {
+                open,
+                start,
+                end,
+                language: language.to_string(),
+            });
+        }
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{blocks, Block, Markup};
+    use crate::{highlight, html_validation, Query};
+    use syntect::util::LinesWithEndings;
+
+    fn find(markup: Markup, code: &str) -> Vec<(usize, usize, usize, String)> {
+        let lines: Vec<&str> = LinesWithEndings::from(code).collect();
+        blocks(markup, &lines)
+            .into_iter()
+            .map(
+                |Block {
+                     open,
+                     start,
+                     end,
+                     language,
+                 }| (open, start, end, language),
+            )
+            .collect()
+    }
+
+    #[test]
+    fn finds_blocks() {
+        let markdown = "# Title\n\n```rust,ignore\nfn main() {}\n```\n\n\
+                        ````{.go}\n```\n````\n\n~~~\nplain\n~~~\n\n    ```indented\n\n```py\nunterminated\n";
+        assert_eq!(
+            find(Markup::Markdown, markdown),
+            vec![
+                (2, 3, 4, "rust".to_string()),
+                (6, 7, 8, "go".to_string()),
+                (16, 17, 18, "py".to_string()),
+            ]
+        );
+
+        let rst = "Title\n=====\n\n.. code-block:: python\n   :linenos:\n\n   def f():\n\n       pass\n\nAfter\n";
+        assert_eq!(
+            find(Markup::RestructuredText, rst),
+            vec![(3, 6, 9, "python".to_string())]
+        );
+    }
+
+    fn css(filepath: &str, code: &str) -> String {
+        let result = highlight(Query {
+            filepath: filepath.to_string(),
+            code: code.to_string(),
+            css: true,
+            ..Query::default()
+        });
+        let html = result["data"].as_str().unwrap().to_string();
+        html_validation::validate(&html, code).unwrap();
+        html
+    }
+
+    #[test]
+    fn highlights_blocks_with_their_language() {
+        let package = "<span class=\"hl-keyword hl-other hl-package hl-go\">package</span>";
+        let html = css(
+            "README.md",
+            "# Title\n\n```go\npackage main\n\nfunc main() {}\n```\n\nAfter *that*.\n",
+        );
+        assert!(html.contains(package), "{}", html);
+
+        let html = css(
+            "README.rst",
+            "Title\n=====\n\n.. code-block:: go\n\n   package main\n\nAfter\n",
+        );
+        assert!(html.contains(package), "{}", html);
+
+        // The text after the block is highlighted as if the block wasn't.
+        let after = |html: &str| html.rsplit("<tr>").next().unwrap().to_string();
+        let code = "# Title\n\n```haskell\nmain = print 1\n```\n\nAfter *that*.\n";
+        let plain = "# Title\n\n```\nmain = print 1\n```\n\nAfter *that*.\n";
+        assert_eq!(after(&css("a.md", code)), after(&css("a.md", plain)));
+    }
+
+    #[test]
+    fn highlights_blocks_in_every_mode() {
+        let code = "# Title\n\n```go\npackage main\n```\n";
+        let plain = "# Title\n\n```\npackage main\n```\n";
+        let row = |query: Query| {
+            let html = highlight(query)["data"].as_str().unwrap().to_string();
+            html.split("<tr>").nth(4).unwrap().to_string()
+        };
+        let themed = |code: &str| {
+            row(Query {
+                filepath: "README.md".to_string(),
+                code: code.to_string(),
+                theme: "InspiredGitHub".to_string(),
+                table: true,
+                ..Query::default()
+            })
+        };
+        assert_ne!(themed(code), themed(plain));
+
+        let incremental = row(Query {
+            filepath: "README.md".to_string(),
+            code: code.to_string(),
+            css: true,
+            document_id: Some("code_blocks::highlights_blocks_in_every_mode".to_string()),
+            ..Query::default()
+        });
+        assert!(
+            incremental
+                .contains("<span class=\"hl-keyword hl-other hl-package hl-go\">package</span>"),
+            "{}",
+            incremental
+        );
+    }
+}
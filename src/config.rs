@@ -0,0 +1,45 @@
+use std::env;
+use std::str::FromStr;
+
+/// env_or returns the value of the environment variable `key` parsed as a T,
+/// or `default` if the variable is unset or cannot be parsed.
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// env_flag reports whether the environment variable `key` is set to a value
+/// other than "", "0" or "false", e.g. `1`, `true` or `yes`.
+pub fn env_flag(key: &str) -> bool {
+    env::var(key).map_or(false, |v| {
+        let v = v.trim();
+        !v.is_empty() && v != "0" && !v.eq_ignore_ascii_case("false")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::env_flag;
+    use std::env;
+
+    #[test]
+    fn flags() {
+        let key = "CONFIG_TESTS_FLAG";
+        assert!(!env_flag(key));
+        for &(value, set) in &[
+            ("", false),
+            ("0", false),
+            ("false", false),
+            ("FALSE", false),
+            ("1", true),
+            ("true", true),
+            ("yes", true),
+        ] {
+            env::set_var(key, value);
+            assert_eq!(env_flag(key), set, "{:?}", value);
+        }
+        env::remove_var(key);
+    }
+}
@@ -1,3 +1,9 @@
+use crate::class_map::{self, ClassMap};
+use crate::config::env_or;
+use crate::engine::{ParsedLine, ParsedLines};
+use rayon::prelude::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::Write;
 use syntect::{
     html::ClassStyle,
@@ -8,6 +14,71 @@ use syntect::{
     util::LinesWithEndings,
 };
 
+lazy_static! {
+    // Documents with at least this many lines are rendered in parallel, in
+    // chunks of PARALLEL_CHUNK_LINES lines.
+    static ref PARALLEL_MIN_LINES: usize = env_or("PARALLEL_MIN_LINES", 5000);
+    static ref PARALLEL_CHUNK_LINES: usize = env_or("PARALLEL_CHUNK_LINES", 1000).max(1);
+}
+
+lazy_static! {
+    static ref SOURCE: Scope = Scope::new("source").unwrap();
+    static ref TEXT: Scope = Scope::new("text").unwrap();
+    static ref META: Scope = Scope::new("meta").unwrap();
+}
+
+/// ClassNames determines the classes of the spans in the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClassNames {
+    /// Every atom of every scope, with a prefix, e.g. `hl-source hl-go` and
+    /// `hl-keyword hl-other hl-package hl-go`.
+    Full { prefix: &'static str },
+
+    /// Like Full, but without the language atom at the end of scopes (e.g.
+    /// `hl-keyword hl-other hl-package`), and without spans that would have
+    /// the same classes as the span they're nested in. If skip_wrappers is
+    /// set, there are no spans for `source.*`, `text.*` and `meta.*` scopes,
+    /// which stylesheets rarely style, and so they aren't reopened on every
+    /// line either.
+    Compact {
+        prefix: &'static str,
+        skip_wrappers: bool,
+    },
+
+    /// The classes of another highlighter's stylesheets, e.g. `k` for
+    /// Pygments or `hljs-keyword` for highlight.js, which the class map
+    /// assigns to scopes. Like in Compact mode, there are no spans that would
+    /// have the same classes as the span they're nested in.
+    Mapped(&'static ClassMap),
+}
+
+impl ClassNames {
+    /// from_name returns the class names of the given name in queries.
+    pub fn from_name(name: &str, prefix: &'static str) -> Option<Self> {
+        match name {
+            "" | "full" => Some(ClassNames::Full { prefix }),
+            "compact" => Some(ClassNames::Compact {
+                prefix,
+                skip_wrappers: false,
+            }),
+            "compact_unwrapped" => Some(ClassNames::Compact {
+                prefix,
+                skip_wrappers: true,
+            }),
+            _ => class_map::get(name).map(ClassNames::Mapped),
+        }
+    }
+}
+
+impl From<ClassStyle> for ClassNames {
+    fn from(style: ClassStyle) -> Self {
+        match style {
+            ClassStyle::SpacedPrefixed { prefix } => ClassNames::Full { prefix },
+            _ => ClassNames::Full { prefix: "" },
+        }
+    }
+}
+
 /// The ClassedTableGenerator generates HTML tables of the following form:
 /// <table>
 ///   <tbody>
@@ -28,9 +99,7 @@ use syntect::{
 pub struct ClassedTableGenerator<'a> {
     syntax_set: &'a SyntaxSet,
     parse_state: ParseState,
-    stack: ScopeStack,
-    html: String,
-    style: ClassStyle,
+    writer: SpanWriter,
     code: &'a str,
     max_line_len: Option<usize>,
 }
@@ -41,62 +110,304 @@ impl<'a> ClassedTableGenerator<'a> {
         sr: &SyntaxReference,
         code: &'a str,
         max_line_len: Option<usize>,
-        style: ClassStyle,
+        style: impl Into<ClassNames>,
     ) -> Self {
         ClassedTableGenerator {
             code,
             syntax_set: ss,
             parse_state: ParseState::new(sr),
-            stack: ScopeStack::new(),
-            html: String::with_capacity(code.len() * 8), // size is a best guess
-            style,
+            writer: SpanWriter::new(ScopeStack::new(), style.into(), code.len() * 8), // size is a best guess
+            max_line_len,
+        }
+    }
+
+    /// resume creates a generator which continues highlighting from a parse
+    /// state and scope stack previously returned by `state`. Lines are fed to
+    /// it one at a time through `highlight_line`.
+    pub fn resume(
+        ss: &'a SyntaxSet,
+        parse_state: ParseState,
+        stack: ScopeStack,
+        max_line_len: Option<usize>,
+        style: impl Into<ClassNames>,
+    ) -> Self {
+        ClassedTableGenerator {
+            code: "",
+            syntax_set: ss,
+            parse_state,
+            writer: SpanWriter::new(stack, style.into(), 0),
             max_line_len,
         }
     }
 
     // generate takes ownership of self so that it can't be re-used
     pub fn generate(mut self) -> String {
-        open_table(&mut self.html);
-
-        for (i, line) in LinesWithEndings::from(self.code).enumerate() {
-            open_row(&mut self.html, i);
-            if self.max_line_len.map_or(false, |n| line.len() > n) {
-                self.write_escaped_html(&line);
-            } else {
-                self.write_spans_for_line(&line);
+        let lines: Vec<&str> = LinesWithEndings::from(self.code).collect();
+        if lines.len() >= *PARALLEL_MIN_LINES {
+            return self.generate_parallel(&lines, *PARALLEL_CHUNK_LINES);
+        }
+
+        open_table(&mut self.writer.html);
+
+        for (i, line) in lines.iter().enumerate() {
+            open_row(&mut self.writer.html, i);
+            self.write_line(line);
+            close_row(&mut self.writer.html);
+        }
+
+        close_table(&mut self.writer.html);
+        self.writer.html
+    }
+
+    // generate_parallel parses the lines in chunks of chunk_lines lines.
+    // Parsing is inherently sequential, but as soon as a chunk is parsed, its
+    // HTML is rendered on the rayon pool, starting from the scope stack at the
+    // start of the chunk, while parsing continues with the next chunk. So only
+    // the ops of the chunks which are yet to be rendered are kept in memory.
+    fn generate_parallel(mut self, lines: &[&str], chunk_lines: usize) -> String {
+        let names = self.writer.names;
+        let mut chunks = vec![String::new(); (lines.len() + chunk_lines - 1) / chunk_lines];
+        let capacity = self.writer.html.capacity() / chunks.len().max(1);
+        rayon::scope(|scope| {
+            for (i, html) in chunks.iter_mut().enumerate() {
+                let start = i * chunk_lines;
+                let chunk = &lines[start..(start + chunk_lines).min(lines.len())];
+                let stack = self.writer.stack.clone();
+                let ops: Vec<ParsedLine> = chunk
+                    .iter()
+                    .map(|line| {
+                        if self.is_too_long(line) {
+                            return None;
+                        }
+                        let ops = self.parse_state.parse_line(line, self.syntax_set);
+                        for (_, op) in &ops {
+                            self.writer.stack.apply(op);
+                        }
+                        Some(ops)
+                    })
+                    .collect();
+                scope.spawn(move |_| {
+                    *html = render_chunk(chunk, &ops, start, stack, names, capacity);
+                });
             }
-            close_row(&mut self.html);
+        });
+        join_chunks(&chunks)
+    }
+
+    /// highlight_line returns the contents of the code cell for the next line
+    /// of the document, advancing the parse state past it.
+    pub fn highlight_line(&mut self, line: &str) -> String {
+        self.writer.html.clear();
+        self.write_line(line);
+        std::mem::take(&mut self.writer.html)
+    }
+
+    /// state returns a snapshot of the parse state and scope stack in between
+    /// two lines, from which highlighting can later be resumed.
+    pub fn state(&self) -> (ParseState, ScopeStack) {
+        (self.parse_state.clone(), self.writer.stack.clone())
+    }
+
+    /// is_at_state reports whether highlighting the remaining lines would
+    /// produce the same output as it would from the given snapshot.
+    pub fn is_at_state(&self, parse_state: &ParseState, stack: &ScopeStack) -> bool {
+        self.parse_state == *parse_state && self.writer.stack == *stack
+    }
+
+    fn is_too_long(&self, line: &str) -> bool {
+        self.max_line_len.map_or(false, |n| line.len() > n)
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.is_too_long(line) {
+            self.writer.write_escaped_html(&line);
+        } else {
+            let parsed_line = self.parse_state.parse_line(line, self.syntax_set);
+            self.writer
+                .write_spans_for_line(line, parsed_line.as_slice());
+        }
+    }
+}
+
+/// table_for_parsed_lines renders the same table as `ClassedTableGenerator`
+/// for code which was already parsed, e.g. by tree-sitter. Lines without ops
+/// are rendered as escaped plain text.
+pub fn table_for_parsed_lines(
+    code: &str,
+    parsed_lines: &ParsedLines,
+    style: impl Into<ClassNames>,
+) -> String {
+    let lines: Vec<&str> = LinesWithEndings::from(code).collect();
+    render_parallel(
+        &lines,
+        parsed_lines,
+        ScopeStack::new(),
+        style.into(),
+        code.len() * 8,
+        *PARALLEL_CHUNK_LINES,
+    )
+}
+
+// render_parallel renders the table for lines which were already parsed. The
+// scope stack at the start of every chunk of lines is recorded by applying
+// the ops of the lines before it, and the HTML of all chunks is then rendered
+// in parallel, and the chunks are joined in order.
+fn render_parallel(
+    lines: &[&str],
+    ops: &[ParsedLine],
+    mut stack: ScopeStack,
+    names: ClassNames,
+    capacity: usize,
+    chunk_lines: usize,
+) -> String {
+    let mut chunk_stacks = Vec::with_capacity(lines.len() / chunk_lines + 1);
+    for (i, parsed_line) in ops.iter().enumerate().take(lines.len()) {
+        if i % chunk_lines == 0 {
+            chunk_stacks.push(stack.clone());
+        }
+        for (_, op) in parsed_line.iter().flatten() {
+            stack.apply(op);
+        }
+    }
+
+    let capacity = capacity / chunk_stacks.len().max(1);
+    let chunks: Vec<String> = chunk_stacks
+        .into_par_iter()
+        .enumerate()
+        .map(|(chunk, stack)| {
+            let start = chunk * chunk_lines;
+            let end = (start + chunk_lines).min(lines.len());
+            render_chunk(
+                &lines[start..end],
+                &ops[start.min(ops.len())..end.min(ops.len())],
+                start,
+                stack,
+                names,
+                capacity,
+            )
+        })
+        .collect();
+    join_chunks(&chunks)
+}
+
+// render_chunk renders the rows of the lines of a chunk, the first of which is
+// line start of the document, starting from the scope stack before it.
+fn render_chunk(
+    lines: &[&str],
+    ops: &[ParsedLine],
+    start: usize,
+    stack: ScopeStack,
+    names: ClassNames,
+    capacity: usize,
+) -> String {
+    let mut writer = SpanWriter::new(stack, names, capacity);
+    for (i, (line, parsed_line)) in lines.iter().zip(ops).enumerate() {
+        open_row(&mut writer.html, start + i);
+        match parsed_line {
+            Some(parsed_line) => writer.write_spans_for_line(line, parsed_line),
+            None => writer.write_escaped_html(line),
         }
+        close_row(&mut writer.html);
+    }
+    writer.html
+}
 
-        close_table(&mut self.html);
-        self.html
+// join_chunks joins the rows rendered for the chunks of a document into its
+// table.
+fn join_chunks(chunks: &[String]) -> String {
+    let mut html = String::with_capacity(chunks.iter().map(String::len).sum::<usize>() + 32);
+    open_table(&mut html);
+    for chunk in chunks {
+        html.push_str(chunk);
+    }
+    close_table(&mut html);
+    html
+}
+
+/// SpanWriter renders the spans for the tokens of a line, keeping track of
+/// the scopes which remain open from one line to the next.
+struct SpanWriter {
+    html: String,
+    stack: ScopeStack,
+    names: ClassNames,
+
+    // For every scope on the stack, whether a span was opened for it in the
+    // current line, and if so with which classes in Compact mode.
+    spans: Vec<Option<String>>,
+}
+
+impl SpanWriter {
+    fn new(stack: ScopeStack, names: ClassNames, capacity: usize) -> Self {
+        SpanWriter {
+            html: String::with_capacity(capacity),
+            stack,
+            names,
+            spans: Vec::new(),
+        }
     }
 
     // open_current_scopes opens a span for every scope that was still
     // open from the last line
     fn open_current_scopes(&mut self) {
-        for scope in self.stack.clone().as_slice() {
-            self.open_scope(scope)
+        let stack = self.stack.clone();
+        let scopes = stack.as_slice();
+        self.spans.clear();
+        for (i, scope) in scopes.iter().enumerate() {
+            self.open_scope(scope, &scopes[..=i]);
         }
     }
 
     fn close_current_scopes(&mut self) {
-        for _ in 0..self.stack.len() {
-            self.close_scope()
+        while let Some(span) = self.spans.pop() {
+            if span.is_some() {
+                self.html.push_str("</span>");
+            }
         }
     }
 
-    fn open_scope(&mut self, scope: &Scope) {
-        self.html.push_str("<span class=\"");
-        self.write_classes_for_scope(scope);
-        self.html.push_str("\">");
-    }
+    // open_scope opens a span for scope, which was just pushed onto the
+    // stack, unless the class names omit it, and returns whether it did.
+    fn open_scope(&mut self, scope: &Scope, stack: &[Scope]) -> bool {
+        let classes = match self.names {
+            ClassNames::Full { prefix } => {
+                self.html.push_str("<span class=\"");
+                let html = &mut self.html;
+                with_classes(scope, prefix, None, |classes| html.push_str(classes));
+                self.html.push_str("\">");
+                self.spans.push(Some(String::new()));
+                return true;
+            }
+            ClassNames::Compact {
+                prefix,
+                skip_wrappers,
+            } => {
+                if skip_wrappers && is_wrapper(scope) {
+                    self.spans.push(None);
+                    return false;
+                }
+                let language = std::iter::once(scope)
+                    .chain(stack.iter().rev())
+                    .find(|s| SOURCE.is_prefix_of(**s) || TEXT.is_prefix_of(**s))
+                    .map(|s| s.atom_at(s.len() as usize - 1));
+                with_classes(scope, prefix, language, str::to_string)
+            }
+            // Classes from a class map file may need escaping.
+            ClassNames::Mapped(map) => map
+                .classes(*scope)
+                .map_or_else(String::new, |classes| Escape(classes).to_string()),
+        };
 
-    fn close_scope(&mut self) {
-        self.html.push_str("</span>");
+        let parent = self.spans.iter().rev().find_map(Option::as_ref);
+        if classes.is_empty() || parent == Some(&classes) {
+            self.spans.push(None);
+            return false;
+        }
+        write!(&mut self.html, "<span class=\"{}\">", classes).unwrap();
+        self.spans.push(Some(classes));
+        true
     }
 
-    fn write_spans_for_line(&mut self, line: &str) {
+    fn write_spans_for_line(&mut self, line: &str, ops: &[(usize, ScopeStackOp)]) {
         // Whenever we highlight a new line, the all scopes that are still open
         // from the last line must be created. Since scope spans can't cross table
         // row boundaries, we need to open and close scope spans that are shared
@@ -105,14 +416,13 @@ impl<'a> ClassedTableGenerator<'a> {
         // For example, for a go file, every line should likely start with
         // <span class="hl-source hl-go">
         self.open_current_scopes();
-        let parsed_line = self.parse_state.parse_line(line, self.syntax_set);
-        self.write_spans_for_tokens(line, parsed_line.as_slice());
+        self.write_spans_for_tokens(line, ops);
         self.close_current_scopes();
     }
 
     // write_spans_for_tokens creates spans for the list of tokens passed to it.
-    // It modifies the stack of the ClassedTableGenerator, adding any scopes
-    // that are unclosed at the end of the line.
+    // It modifies the stack of the SpanWriter, adding any scopes that are
+    // unclosed at the end of the line.
     //
     // This is modified from highlight::tokens_to_classed_spans
     fn write_spans_for_tokens(&mut self, line: &str, ops: &[(usize, ScopeStackOp)]) {
@@ -129,19 +439,23 @@ impl<'a> ClassedTableGenerator<'a> {
                 cur_index = i
             }
             let mut stack = self.stack.clone();
-            stack.apply_with_hook(op, |basic_op, _| match basic_op {
+            stack.apply_with_hook(op, |basic_op, scopes| match basic_op {
                 BasicScopeStackOp::Push(scope) => {
-                    span_start = self.html.len();
-                    span_empty = true;
-                    self.open_scope(&scope);
+                    let start = self.html.len();
+                    if self.open_scope(&scope, scopes) {
+                        span_start = start;
+                        span_empty = true;
+                    }
                 }
                 BasicScopeStackOp::Pop => {
-                    if span_empty {
-                        self.html.truncate(span_start);
-                    } else {
-                        self.close_scope();
+                    if let Some(Some(_)) = self.spans.pop() {
+                        if span_empty {
+                            self.html.truncate(span_start);
+                        } else {
+                            self.html.push_str("</span>");
+                        }
+                        span_empty = false;
                     }
-                    span_empty = false;
                 }
             });
             self.stack = stack;
@@ -149,48 +463,151 @@ impl<'a> ClassedTableGenerator<'a> {
         self.write_escaped_html(&line[cur_index..]);
     }
 
-    // write_classes_for_scope is modified from highlight::scope_to_classes
-    fn write_classes_for_scope(&mut self, scope: &Scope) {
-        let repo = SCOPE_REPO.lock().unwrap();
-        for i in 0..(scope.len()) {
-            let atom = scope.atom_at(i as usize);
-            let atom_s = repo.atom_str(atom);
-            if i != 0 {
-                self.html.push_str(" ")
-            }
-            if let ClassStyle::SpacedPrefixed { prefix } = self.style {
-                self.html.push_str(&prefix)
-            }
-            self.html.push_str(atom_s);
+    fn write_escaped_html(&mut self, s: &str) {
+        write!(&mut self.html, "{}", Escape(s)).unwrap()
+    }
+}
+
+// is_wrapper reports whether scope is a `source.*`, `text.*` or `meta.*`
+// scope, which mostly wrap other scopes.
+fn is_wrapper(scope: &Scope) -> bool {
+    SOURCE.is_prefix_of(*scope) || TEXT.is_prefix_of(*scope) || META.is_prefix_of(*scope)
+}
+
+thread_local! {
+    // Building the classes for a scope requires the global SCOPE_REPO lock,
+    // which would otherwise be taken by every Rocket thread for every span.
+    // The number of distinct scopes is bounded by the grammars, so each
+    // thread simply remembers the class string of every scope it has seen.
+    static CLASSES: RefCell<HashMap<(Scope, &'static str, Option<u16>), String>> = RefCell::new(HashMap::new());
+}
+
+/// with_classes_for_scope calls f with the (cached) class string for scope.
+pub fn with_classes_for_scope<R>(
+    scope: &Scope,
+    style: impl Into<ClassNames>,
+    f: impl FnOnce(&str) -> R,
+) -> R {
+    match style.into() {
+        ClassNames::Full { prefix } | ClassNames::Compact { prefix, .. } => {
+            with_classes(scope, prefix, None, f)
         }
+        ClassNames::Mapped(map) => f(map.classes(*scope).unwrap_or("")),
     }
+}
 
-    fn write_escaped_html(&mut self, s: &str) {
-        write!(&mut self.html, "{}", Escape(s)).unwrap()
+// with_classes calls f with the (cached) class string for scope. If language
+// is set, it is the atom of the language the scope is in, which is omitted
+// from the end of the scope.
+fn with_classes<R>(
+    scope: &Scope,
+    prefix: &'static str,
+    language: Option<u16>,
+    f: impl FnOnce(&str) -> R,
+) -> R {
+    CLASSES.with(|classes| {
+        let mut classes = classes.borrow_mut();
+        let s = classes
+            .entry((*scope, prefix, language))
+            .or_insert_with(|| {
+                let len = scope.len() as usize;
+                match language {
+                    Some(atom) if scope.atom_at(len - 1) == atom => {
+                        atoms_to_classes(scope, len - 1, prefix)
+                    }
+                    _ => atoms_to_classes(scope, len, prefix),
+                }
+            });
+        f(s)
+    })
+}
+
+// scope_to_classes is modified from highlight::scope_to_classes
+pub fn scope_to_classes(scope: &Scope, prefix: &str) -> String {
+    atoms_to_classes(scope, scope.len() as usize, prefix)
+}
+
+// atoms_to_classes returns the classes for the first len atoms of scope.
+fn atoms_to_classes(scope: &Scope, len: usize, prefix: &str) -> String {
+    let repo = SCOPE_REPO.lock().unwrap();
+    let mut s = String::new();
+    for i in 0..len {
+        let atom = scope.atom_at(i);
+        let atom_s = repo.atom_str(atom);
+        if i != 0 {
+            s.push(' ')
+        }
+        s.push_str(prefix);
+        s.push_str(atom_s);
     }
+    s
+}
+
+/// InlineTableStyle holds inline styles for the table elements, for output
+/// which must render without our stylesheet. The line number cells then also
+/// contain the line number as text, rather than relying on a `::before`
+/// rule showing their `data-line`.
+pub struct InlineTableStyle {
+    pub table: String,
+    pub line: String,
+    pub code: String,
 }
 
 fn open_table(s: &mut String) {
-    s.push_str("<table><tbody>");
+    open_table_with_style(s, None)
 }
 
-fn close_table(s: &mut String) {
+pub fn open_table_with_style(s: &mut String, style: Option<&InlineTableStyle>) {
+    match style {
+        Some(style) => write!(s, "<table style=\"{}\"><tbody>", style.table).unwrap(),
+        None => s.push_str("<table><tbody>"),
+    }
+}
+
+pub fn close_table(s: &mut String) {
     s.push_str("</tbody></table>");
 }
 
 fn open_row(s: &mut String, i: usize) {
-    write!(
-        s,
-        "<tr><td class=\"line\" data-line=\"{}\"/><td class=\"code\"><div>",
-        i + 1
-    )
+    open_row_with_style(s, i, None)
+}
+
+pub fn open_row_with_style(s: &mut String, i: usize, style: Option<&InlineTableStyle>) {
+    match style {
+        Some(style) => write!(
+            s,
+            "<tr><td class=\"line\" data-line=\"{0}\" style=\"{1}\">{0}</td><td class=\"code\" style=\"{2}\"><div>",
+            i + 1,
+            style.line,
+            style.code
+        ),
+        None => write!(
+            s,
+            "<tr><td class=\"line\" data-line=\"{}\"/><td class=\"code\"><div>",
+            i + 1
+        ),
+    }
     .unwrap();
 }
 
-fn close_row(s: &mut String) {
+pub fn close_row(s: &mut String) {
     s.push_str("</div></td></tr>");
 }
 
+/// table_from_rows assembles a table from the code cell contents of each line,
+/// as returned by `ClassedTableGenerator::highlight_line`.
+pub fn table_from_rows(rows: &[String]) -> String {
+    let mut html = String::with_capacity(rows.iter().map(|r| r.len() + 64).sum());
+    open_table(&mut html);
+    for (i, row) in rows.iter().enumerate() {
+        open_row(&mut html, i);
+        html.push_str(row);
+        close_row(&mut html);
+    }
+    close_table(&mut html);
+    html
+}
+
 use std::fmt;
 
 /// Wrapper struct which will emit the HTML-escaped version of the contained
@@ -234,7 +651,9 @@ impl<'a> fmt::Display for Escape<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{highlight, Query};
+    use super::{ClassNames, ClassedTableGenerator};
+    use crate::{highlight, html_validation, Query};
+    use syntect::{html::ClassStyle, parsing::SyntaxSet, util::LinesWithEndings};
 
     fn test_css_table_highlight(q: Query, expected: &str) {
         let result = highlight(q);
@@ -247,9 +666,8 @@ mod tests {
             filepath: "test.go".to_string(),
             code: "package main\n".to_string(),
             line_length_limit: None,
-            extension: String::new(),
-            theme: String::new(),
             css: true,
+            ..Query::default()
         };
         let expected = "<table>\
                             <tbody>\
@@ -276,9 +694,8 @@ mod tests {
             filepath: "test.html".to_string(),
             code: "<div>test</div>".to_string(),
             line_length_limit: Some(10),
-            extension: String::new(),
-            theme: String::new(),
             css: true,
+            ..Query::default()
         };
         let expected = "<table>\
                             <tbody>\
@@ -299,9 +716,8 @@ mod tests {
             filepath: "test.go".to_string(),
             code: "package main\n".to_string(),
             line_length_limit: Some(5),
-            extension: String::new(),
-            theme: String::new(),
             css: true,
+            ..Query::default()
         };
         let expected = "<table>\
                             <tbody>\
@@ -323,9 +739,8 @@ mod tests {
             code: "package com.lwl.boot.model;\n\npublic class Item implements Serializable {}"
                 .to_string(),
             line_length_limit: None,
-            extension: String::new(),
-            theme: String::new(),
             css: true,
+            ..Query::default()
         };
         let expected = "<table>\
                             <tbody>\
@@ -391,4 +806,166 @@ mod tests {
                         </table>";
         test_css_table_highlight(query, expected)
     }
+
+    #[test]
+    fn compact_css() {
+        let query = |classes: &str| Query {
+            filepath: "test.go".to_string(),
+            code: "package main\n".to_string(),
+            css: true,
+            classes: classes.to_string(),
+            ..Query::default()
+        };
+        let row = |cell: &str| {
+            format!(
+                "<table><tbody><tr><td class=\"line\" data-line=\"1\"/><td class=\"code\"><div>{}</div></td></tr></tbody></table>",
+                cell
+            )
+        };
+        test_css_table_highlight(
+            query("compact"),
+            &row("<span class=\"hl-source\">\
+                      <span class=\"hl-keyword hl-other hl-package\">package</span> \
+                      <span class=\"hl-variable hl-other\">main</span>\n\
+                  </span>"),
+        );
+        test_css_table_highlight(
+            query("compact_unwrapped"),
+            &row(
+                "<span class=\"hl-keyword hl-other hl-package\">package</span> \
+                  <span class=\"hl-variable hl-other\">main</span>\n",
+            ),
+        );
+        assert_eq!(
+            highlight(query("unknown")),
+            json!({"error": "invalid classes", "code": "invalid_classes"})
+        );
+    }
+
+    #[test]
+    fn mapped_css() {
+        let query = |classes: &str| Query {
+            filepath: "test.go".to_string(),
+            code: "package main\n".to_string(),
+            css: true,
+            classes: classes.to_string(),
+            ..Query::default()
+        };
+        let row = |cell: &str| {
+            format!(
+                "<table><tbody><tr><td class=\"line\" data-line=\"1\"/><td class=\"code\"><div>{}</div></td></tr></tbody></table>",
+                cell
+            )
+        };
+        test_css_table_highlight(
+            query("pygments"),
+            &row("<span class=\"kn\">package</span> <span class=\"n\">main</span>\n"),
+        );
+        test_css_table_highlight(
+            query("hljs"),
+            &row("<span class=\"hljs-keyword\">package</span> main\n"),
+        );
+    }
+
+    #[test]
+    fn compact_unwrapped_java() {
+        let query = Query {
+            filepath: "test.java".to_string(),
+            code: "package com.lwl.boot.model;\n\npublic class Item implements Serializable {}"
+                .to_string(),
+            css: true,
+            classes: "compact_unwrapped".to_string(),
+            ..Query::default()
+        };
+        let expected = "<table>\
+                            <tbody>\
+                                <tr>\
+                                    <td class=\"line\" data-line=\"1\"/>\
+                                    <td class=\"code\">\
+                                        <div>\
+                                            <span class=\"hl-keyword hl-other hl-package\">package</span> \
+                                            <span class=\"hl-entity hl-name hl-namespace\">\
+                                                com\
+                                                <span class=\"hl-punctuation hl-accessor hl-dot\">.</span>\
+                                                lwl\
+                                                <span class=\"hl-punctuation hl-accessor hl-dot\">.</span>\
+                                                boot\
+                                                <span class=\"hl-punctuation hl-accessor hl-dot\">.</span>\
+                                                model\
+                                            </span>\
+                                            <span class=\"hl-punctuation hl-terminator\">;</span>\n\
+                                        </div>\
+                                    </td>\
+                                </tr>\
+                                <tr>\
+                                    <td class=\"line\" data-line=\"2\"/>\
+                                    <td class=\"code\"><div>\n</div></td>\
+                                </tr>\
+                                <tr>\
+                                    <td class=\"line\" data-line=\"3\"/>\
+                                    <td class=\"code\">\
+                                        <div>\
+                                            <span class=\"hl-storage hl-modifier\">public</span> \
+                                            <span class=\"hl-storage hl-type\">class</span> \
+                                            <span class=\"hl-entity hl-name hl-class\">Item</span> \
+                                            <span class=\"hl-keyword hl-declaration hl-implements\">implements</span> \
+                                            <span class=\"hl-entity hl-other hl-inherited-class\">Serializable</span> \
+                                            <span class=\"hl-punctuation hl-section hl-block hl-begin\">{</span>\
+                                            <span class=\"hl-punctuation hl-section hl-block hl-end\">}</span>\
+                                        </div>\
+                                    </td>\
+                                </tr>\
+                            </tbody>\
+                        </table>";
+        test_css_table_highlight(query, expected)
+    }
+
+    #[test]
+    fn compact_is_smaller() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        for (extension, code) in &[
+            ("java", include_str!("../bench/corpus/java.java")),
+            ("rs", include_str!("../bench/corpus/rust.rs")),
+            ("md", include_str!("../bench/corpus/markdown.md")),
+        ] {
+            let sr = ss.find_syntax_by_extension(extension).unwrap();
+            let generate = |names: ClassNames| {
+                ClassedTableGenerator::new(&ss, sr, code, None, names).generate()
+            };
+            let full = generate(ClassNames::Full { prefix: "hl-" });
+            let compact = generate(ClassNames::Compact {
+                prefix: "hl-",
+                skip_wrappers: false,
+            });
+            let unwrapped = generate(ClassNames::Compact {
+                prefix: "hl-",
+                skip_wrappers: true,
+            });
+            for html in &[&full, &compact, &unwrapped] {
+                html_validation::validate(html, code).unwrap();
+            }
+            assert!(compact.len() < full.len(), "{}", extension);
+            assert!(unwrapped.len() < compact.len(), "{}", extension);
+        }
+    }
+
+    #[test]
+    fn parallel_matches_sequential() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let sr = ss.find_syntax_by_extension("java").unwrap();
+        let code = "/**\n * A comment spanning\n * several lines.\n */\n\
+                    package com.lwl.boot.model;\n\n\
+                    public class Item implements Serializable {\n\
+                    \tString s = \"a <b> & c\";\n\
+                    }\n";
+        let style = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+        let lines: Vec<&str> = LinesWithEndings::from(code).collect();
+
+        let sequential = ClassedTableGenerator::new(&ss, sr, code, Some(30), style).generate();
+        for &chunk_lines in &[1, 2, 3, lines.len()] {
+            let parallel = ClassedTableGenerator::new(&ss, sr, code, Some(30), style)
+                .generate_parallel(&lines, chunk_lines);
+            assert_eq!(sequential, parallel, "chunk_lines = {}", chunk_lines);
+        }
+    }
 }
@@ -0,0 +1,207 @@
+use crate::code_blocks;
+use crate::config::env_or;
+#[cfg(feature = "tree-sitter-backend")]
+use crate::treesitter;
+use syntect::{
+    parsing::{ParseState, ScopeStackOp, SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+lazy_static! {
+    // The syntaxes which are highlighted with tree-sitter unless the request
+    // asks for another engine, e.g. `Go,Rust`.
+    static ref TREE_SITTER_LANGUAGES: Vec<String> = env_or("TREE_SITTER_LANGUAGES", String::new())
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect();
+}
+
+/// ParsedLine holds the scope stack ops of a line, or None if the line is not
+/// highlighted.
+pub type ParsedLine = Option<Vec<(usize, ScopeStackOp)>>;
+
+/// ParsedLines holds the ParsedLine of every line of a document.
+pub type ParsedLines = Vec<ParsedLine>;
+
+/// Engine is the parser which determines the scopes of the code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    Syntect,
+    TreeSitter,
+}
+
+impl Engine {
+    /// select returns the engine to highlight code of the given syntax with,
+    /// given the name of the engine requested, or "" for the configured one.
+    /// Tree-sitter is only selected if it is compiled in and has a grammar for
+    /// the syntax, otherwise syntect is used. Unknown names return None.
+    pub fn select(name: &str, syntax: &SyntaxReference) -> Option<Self> {
+        let tree_sitter = match name {
+            "" => TREE_SITTER_LANGUAGES.iter().any(|l| *l == syntax.name),
+            "syntect" => false,
+            "tree-sitter" => true,
+            _ => return None,
+        };
+        if tree_sitter && tree_sitter_supports(&syntax.name) {
+            Some(Engine::TreeSitter)
+        } else {
+            Some(Engine::Syntect)
+        }
+    }
+}
+
+/// parse returns the ParsedLine of every line of the code, where lines longer
+/// than max_line_len are not highlighted. Syntect parses each line as the
+/// iterator advances, unless the code must be parsed upfront, see
+/// `parse_upfront`.
+pub fn parse<'a>(
+    engine: Engine,
+    code: &'a str,
+    ss: &'a SyntaxSet,
+    syntax: &SyntaxReference,
+    max_line_len: Option<usize>,
+) -> Box<dyn Iterator<Item = ParsedLine> + 'a> {
+    match parse_upfront(engine, code, ss, syntax, max_line_len) {
+        Some(parsed_lines) => Box::new(parsed_lines.into_iter()),
+        None => Box::new(parse_syntect(code, ss, syntax, max_line_len)),
+    }
+}
+
+/// parse_upfront returns the ParsedLines of the code if it must be parsed as a
+/// whole, rather than line by line: when tree-sitter parses it, or when code
+/// blocks in markup are highlighted with the syntax of their language. If
+/// tree-sitter fails, the code is parsed with syntect instead.
+pub fn parse_upfront(
+    engine: Engine,
+    code: &str,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+    max_line_len: Option<usize>,
+) -> Option<ParsedLines> {
+    if engine == Engine::TreeSitter {
+        if let Some(parsed_lines) = parse_tree_sitter(code, syntax, max_line_len) {
+            return Some(parsed_lines);
+        }
+    }
+    if code_blocks::has_code_blocks(syntax) {
+        let mut parsed_lines: ParsedLines = parse_syntect(code, ss, syntax, max_line_len).collect();
+        code_blocks::highlight(code, ss, syntax, &mut parsed_lines);
+        return Some(parsed_lines);
+    }
+    None
+}
+
+fn parse_syntect<'a>(
+    code: &'a str,
+    ss: &'a SyntaxSet,
+    syntax: &SyntaxReference,
+    max_line_len: Option<usize>,
+) -> impl Iterator<Item = ParsedLine> + 'a {
+    let mut parse_state = ParseState::new(syntax);
+    LinesWithEndings::from(code).map(move |line| {
+        if max_line_len.map_or(false, |n| line.len() > n) {
+            None
+        } else {
+            Some(parse_state.parse_line(line, ss))
+        }
+    })
+}
+
+// parse_tree_sitter returns the ParsedLines of the code as parsed by
+// tree-sitter, or None if tree-sitter has no grammar for the syntax or
+// failed.
+#[cfg(feature = "tree-sitter-backend")]
+fn parse_tree_sitter(
+    code: &str,
+    syntax: &SyntaxReference,
+    max_line_len: Option<usize>,
+) -> Option<ParsedLines> {
+    treesitter::parse(code, syntax, max_line_len)
+}
+
+#[cfg(not(feature = "tree-sitter-backend"))]
+fn parse_tree_sitter(
+    _code: &str,
+    _syntax: &SyntaxReference,
+    _max_line_len: Option<usize>,
+) -> Option<ParsedLines> {
+    None
+}
+
+#[cfg(feature = "tree-sitter-backend")]
+fn tree_sitter_supports(syntax: &str) -> bool {
+    treesitter::supports(syntax)
+}
+
+#[cfg(not(feature = "tree-sitter-backend"))]
+fn tree_sitter_supports(_syntax: &str) -> bool {
+    false
+}
+
+/// net_ops returns ops with the same effect on the scope stack as the given
+/// ones, all at offset. Ops other than pushes and pops are dropped.
+pub fn net_ops(offset: usize, ops: Vec<(usize, ScopeStackOp)>) -> Vec<(usize, ScopeStackOp)> {
+    let mut pops = 0;
+    let mut pushed = Vec::new();
+    for (_, op) in ops {
+        match op {
+            ScopeStackOp::Push(scope) => pushed.push(scope),
+            ScopeStackOp::Pop(n) => {
+                for _ in 0..n {
+                    if pushed.pop().is_none() {
+                        pops += 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut net = Vec::with_capacity(pushed.len() + 1);
+    if pops > 0 {
+        net.push((offset, ScopeStackOp::Pop(pops)));
+    }
+    net.extend(
+        pushed
+            .into_iter()
+            .map(|scope| (offset, ScopeStackOp::Push(scope))),
+    );
+    net
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Engine};
+    use syntect::parsing::SyntaxSet;
+
+    #[test]
+    fn select_falls_back_to_syntect() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let go = ss.find_syntax_by_extension("go").unwrap();
+        let plaintext = ss.find_syntax_plain_text();
+        assert_eq!(Engine::select("syntect", go), Some(Engine::Syntect));
+        assert_eq!(
+            Engine::select("tree-sitter", plaintext),
+            Some(Engine::Syntect)
+        );
+        assert_eq!(Engine::select("", plaintext), Some(Engine::Syntect));
+        assert_eq!(Engine::select("vim", go), None);
+        if cfg!(feature = "tree-sitter-backend") {
+            assert_eq!(Engine::select("tree-sitter", go), Some(Engine::TreeSitter));
+        } else {
+            assert_eq!(Engine::select("tree-sitter", go), Some(Engine::Syntect));
+        }
+    }
+
+    #[test]
+    fn long_lines_are_not_parsed() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let go = ss.find_syntax_by_extension("go").unwrap();
+        let code = "package main\n\nvar s = \"a long line\"\n";
+        let parsed: Vec<_> = parse(Engine::Syntect, code, &ss, go, Some(15)).collect();
+        assert_eq!(parsed.len(), 3);
+        assert!(parsed[0].is_some());
+        assert!(parsed[2].is_none());
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::fs;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+
+// ALIASES maps the names of interpreters, Vim filetypes and Emacs modes to the
+// syntax name or file extension they stand for, where looking the name itself
+// up doesn't find the right syntax. Names are lowercase, and version suffixes
+// such as the `3` of `python3` are stripped before looking them up here.
+const ALIASES: &[(&str, &str)] = &[
+    // Interpreters.
+    ("ash", "sh"),
+    ("dash", "sh"),
+    ("deno", "ts"),
+    ("jruby", "rb"),
+    ("ksh", "sh"),
+    ("luajit", "lua"),
+    ("mksh", "sh"),
+    ("node", "js"),
+    ("nodejs", "js"),
+    ("osascript", "applescript"),
+    ("pwsh", "ps1"),
+    ("pypy", "py"),
+    ("rscript", "r"),
+    ("runghc", "hs"),
+    ("runhaskell", "hs"),
+    ("tclsh", "tcl"),
+    ("ts-node", "ts"),
+    ("wish", "tcl"),
+    // Vim filetypes.
+    ("dosini", "ini"),
+    ("gitcommit", "Git Commit"),
+    ("javascript", "js"),
+    ("javascriptreact", "jsx"),
+    ("objc", "m"),
+    ("objcpp", "mm"),
+    ("plaintex", "tex"),
+    ("rust", "rs"),
+    ("typescript", "ts"),
+    ("typescriptreact", "tsx"),
+    // Emacs modes.
+    ("cperl", "pl"),
+    ("elisp", "el"),
+    ("emacs-lisp", "el"),
+    ("js2", "js"),
+    ("shell-script", "sh"),
+];
+
+lazy_static! {
+    // The built-in ALIASES, extended or overridden by LANGUAGE_HINTS_FILE.
+    static ref ALIAS_MAP: HashMap<String, String> = load(std::env::var("LANGUAGE_HINTS_FILE").ok());
+}
+
+fn load(file: Option<String>) -> HashMap<String, String> {
+    let mut aliases: HashMap<String, String> = ALIASES
+        .iter()
+        .map(|&(name, syntax)| (name.to_string(), syntax.to_string()))
+        .collect();
+    if let Some(file) = file {
+        if let Err(err) = load_file(&file, &mut aliases) {
+            eprintln!("LANGUAGE_HINTS_FILE {}: {}", file, err);
+        }
+    }
+    aliases
+}
+
+fn load_file(file: &str, aliases: &mut HashMap<String, String>) -> Result<(), String> {
+    let data = fs::read_to_string(file).map_err(|err| err.to_string())?;
+    let custom: HashMap<String, String> =
+        serde_json::from_str(&data).map_err(|err| err.to_string())?;
+    for (name, syntax) in custom {
+        aliases.insert(name.to_lowercase(), syntax);
+    }
+    Ok(())
+}
+
+/// detect returns the syntax named by a modeline or the shebang of the code,
+/// if any. Modelines take precedence over the shebang, as they name the
+/// language explicitly.
+pub fn detect<'a>(ss: &'a SyntaxSet, code: &str) -> Option<&'a SyntaxReference> {
+    modeline(code)
+        .and_then(|name| lookup(ss, &ALIAS_MAP, name))
+        .or_else(|| shebang(code).and_then(|name| lookup(ss, &ALIAS_MAP, name)))
+}
+
+// lookup returns the syntax of the name, via its alias if it has one, or else
+// the syntax with the name or file extension.
+fn lookup<'a>(
+    ss: &'a SyntaxSet,
+    aliases: &HashMap<String, String>,
+    name: &str,
+) -> Option<&'a SyntaxReference> {
+    let name = name.to_lowercase();
+    let unversioned = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    for &candidate in &[name.as_str(), unversioned] {
+        // Some syntaxes have an empty extension, which must not match.
+        if candidate.is_empty() {
+            continue;
+        }
+        let token = aliases.get(candidate).map_or(candidate, String::as_str);
+        if let Some(syntax) = ss.find_syntax_by_token(token) {
+            return Some(syntax);
+        }
+    }
+    None
+}
+
+// shebang returns the name of the interpreter in the shebang of the code, e.g.
+// `python3` for `#!/usr/bin/python3` and `deno` for
+// `#!/usr/bin/env -S deno run`.
+fn shebang(code: &str) -> Option<&str> {
+    let line = code.lines().next()?.strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+    let mut interpreter = words.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        // Skip the options and variable assignments of env.
+        interpreter = words.find(|w| !w.starts_with('-') && !w.contains('='))?;
+        interpreter = interpreter.rsplit('/').next()?;
+    }
+    Some(interpreter).filter(|i| !i.is_empty())
+}
+
+// modeline returns the filetype of a Vim modeline or the mode of an Emacs
+// `-*-` line in the first or last five lines of the code, where editors look
+// for them.
+fn modeline(code: &str) -> Option<&str> {
+    code.lines()
+        .take(5)
+        .chain(code.lines().rev().take(5))
+        .find_map(|line| vim_filetype(line).or_else(|| emacs_mode(line)))
+}
+
+// vim_filetype returns the filetype of a modeline such as `# vim: ft=ruby` or
+// `/* vim: set filetype=c ts=4: */`.
+fn vim_filetype(line: &str) -> Option<&str> {
+    for marker in &["vim:", "vi:", "ex:"] {
+        for (i, _) in line.match_indices(marker) {
+            // The marker must start the line or follow whitespace, so that
+            // e.g. `regex:` isn't mistaken for one.
+            if i > 0 && !line[..i].ends_with(char::is_whitespace) {
+                continue;
+            }
+            let options = &line[i + marker.len()..];
+            let filetype = options
+                .split(|c: char| c.is_whitespace() || c == ':')
+                .find_map(|option| {
+                    let mut option = option.splitn(2, '=');
+                    match (option.next()?, option.next()?) {
+                        ("ft", value)
+                        | ("filetype", value)
+                        | ("syn", value)
+                        | ("syntax", value)
+                            if !value.is_empty() =>
+                        {
+                            Some(value)
+                        }
+                        _ => None,
+                    }
+                });
+            if filetype.is_some() {
+                return filetype;
+            }
+        }
+    }
+    None
+}
+
+// emacs_mode returns the mode of a line such as `# -*- mode: python -*-` or
+// `-*- ruby -*-`.
+fn emacs_mode(line: &str) -> Option<&str> {
+    let start = line.find("-*-")? + "-*-".len();
+    let end = start + line[start..].find("-*-")?;
+    let variables = line[start..end].trim();
+    if !variables.contains(':') {
+        return Some(variables).filter(|mode| !mode.is_empty());
+    }
+    variables.split(';').find_map(|variable| {
+        let mut variable = variable.splitn(2, ':');
+        let name = variable.next()?.trim();
+        let value = variable.next()?.trim();
+        if name.eq_ignore_ascii_case("mode") && !value.is_empty() {
+            Some(value)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, emacs_mode, lookup, shebang, vim_filetype, ALIAS_MAP};
+    use crate::{highlight, Query};
+    use std::collections::HashMap;
+    use syntect::parsing::SyntaxSet;
+
+    #[test]
+    fn shebangs_and_modelines() {
+        assert_eq!(shebang("#!/usr/bin/python3\n"), Some("python3"));
+        assert_eq!(shebang("#!/usr/bin/env -S deno run\n"), Some("deno"));
+        assert_eq!(shebang("#!/usr/bin/env FOO=1 node\n"), Some("node"));
+        assert_eq!(shebang("#! /bin/sh -e\n"), Some("sh"));
+        assert_eq!(shebang("# not a shebang\n"), None);
+
+        assert_eq!(vim_filetype("# vim: ft=ruby"), Some("ruby"));
+        assert_eq!(vim_filetype("/* vim: set filetype=c ts=4: */"), Some("c"));
+        assert_eq!(vim_filetype("// vi:noai:sw=3 syntax=go"), Some("go"));
+        assert_eq!(vim_filetype("let regex: ft=ruby"), None);
+        assert_eq!(vim_filetype("# vim: ts=4"), None);
+
+        assert_eq!(emacs_mode("# -*- mode: python -*-"), Some("python"));
+        assert_eq!(
+            emacs_mode(";; -*- coding: utf-8; Mode: emacs-lisp -*-"),
+            Some("emacs-lisp")
+        );
+        assert_eq!(emacs_mode("-*- ruby -*-"), Some("ruby"));
+        assert_eq!(emacs_mode("-*- coding: utf-8 -*-"), None);
+    }
+
+    #[test]
+    fn detects_syntaxes() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let name = |code| detect(&ss, code).map(|s| s.name.as_str());
+        assert_eq!(
+            name("#!/usr/bin/env -S deno run\nconsole.log(1)\n"),
+            Some("TypeScript")
+        );
+        assert_eq!(name("#!/usr/bin/python3.9\nprint(1)\n"), Some("Python"));
+        assert_eq!(name("puts 1\n# vim: ft=ruby\n"), Some("Ruby"));
+        assert_eq!(
+            name("#!/bin/sh\n# -*- mode: python -*-\nprint(1)\n"),
+            Some("Python")
+        );
+        assert_eq!(name("#!/usr/bin/unknown\n"), None);
+        assert_eq!(name("no hints\n"), None);
+
+        // Configured aliases take precedence over syntax names.
+        let mut aliases = HashMap::new();
+        aliases.insert("python".to_string(), "Ruby".to_string());
+        assert_eq!(
+            lookup(&ss, &aliases, "python").map(|s| s.name.as_str()),
+            Some("Ruby")
+        );
+        assert_eq!(
+            lookup(&ss, &ALIAS_MAP, "python3").map(|s| s.name.as_str()),
+            Some("Python")
+        );
+    }
+
+    #[test]
+    fn modelines_take_precedence_over_shebangs_and_first_lines() {
+        let html = |filepath: &str| {
+            let result = highlight(Query {
+                filepath: filepath.to_string(),
+                code: "#!/bin/sh\n# -*- mode: python -*-\nprint(1)\n".to_string(),
+                css: true,
+                ..Query::default()
+            });
+            assert_eq!(result["plaintext"], false);
+            result["data"].as_str().unwrap().to_string()
+        };
+        // Shell's grammar claims the `#!/bin/sh` line, but the modeline wins.
+        let script = html("bin/script");
+        assert!(script.contains("hl-python"), "{}", script);
+        assert!(!script.contains("hl-shell"), "{}", script);
+        // The extension still comes first.
+        let shell = html("script.sh");
+        assert!(shell.contains("hl-shell"), "{}", shell);
+    }
+}
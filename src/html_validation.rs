@@ -0,0 +1,266 @@
+//! Structural validation of the HTML generated by `ClassedTableGenerator`.
+//!
+//! Random and adversarial inputs are highlighted with many syntaxes, and the
+//! output is checked to be balanced HTML whose text content is exactly the
+//! input, with `<`, `>` and `&` never appearing unescaped.
+
+use crate::css_table::{ClassNames, ClassedTableGenerator};
+use syntect::{parsing::SyntaxSet, util::LinesWithEndings};
+
+const CLASS_NAMES: &[ClassNames] = &[
+    ClassNames::Full { prefix: "hl-" },
+    ClassNames::Compact {
+        prefix: "hl-",
+        skip_wrappers: false,
+    },
+    ClassNames::Compact {
+        prefix: "hl-",
+        skip_wrappers: true,
+    },
+];
+
+const EXTENSIONS: &[&str] = &[
+    "go", "java", "py", "rs", "js", "ts", "tsx", "c", "cpp", "cs", "rb", "php", "sh", "pl", "lua",
+    "html", "xml", "css", "md", "json", "yaml", "toml", "sql", "hs", "txt",
+];
+
+// Fragments which tend to open or close scopes, or which need escaping.
+const FRAGMENTS: &[&str] = &[
+    "\"",
+    "'",
+    "`",
+    "\"\"\"",
+    "<",
+    ">",
+    "&",
+    "&amp;",
+    "&#39;",
+    "</span>",
+    "<div>",
+    "<!--",
+    "-->",
+    "<script>",
+    "</script>",
+    "/*",
+    "*/",
+    "//",
+    "#",
+    "--",
+    "(",
+    ")",
+    "{",
+    "}",
+    "[",
+    "]",
+    "\\",
+    "\\\"",
+    "${",
+    "#{",
+    "r#\"",
+    "\n",
+    "\r\n",
+    "\r",
+    "\t",
+    " ",
+    "\0",
+    "é",
+    "💥",
+    "\u{200b}",
+    "<<EOF\n",
+    "EOF\n",
+    "```",
+    "fn",
+    "func",
+    "class",
+    "if",
+    "return",
+    "=",
+    "x",
+    "0x1F",
+    "1.5e3",
+];
+
+/// Rng is a small xorshift generator with a fixed seed, so that failures are
+/// reproducible without pulling in a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn random_input(rng: &mut Rng) -> String {
+    let mut s = String::new();
+    for _ in 0..rng.below(64) {
+        s.push_str(FRAGMENTS[rng.below(FRAGMENTS.len())]);
+    }
+    s
+}
+
+/// validate checks that html is a well-formed table of highlighted code whose
+/// text content equals code, returning a description of the first problem.
+pub fn validate(html: &str, code: &str) -> Result<(), String> {
+    let mut stack: Vec<&str> = Vec::new();
+    let mut text = String::new();
+    let mut rows = 0;
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let end = rest
+                .find('>')
+                .ok_or_else(|| format!("unterminated tag at {:?}", rest))?;
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+            if tag.contains('<') || tag.matches('"').count() % 2 != 0 {
+                return Err(format!("malformed tag <{}>", tag));
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                match stack.pop() {
+                    Some(open) if open == name => {}
+                    open => return Err(format!("</{}> closes {:?}", name, open)),
+                }
+                continue;
+            }
+            let name = tag.split(' ').next().unwrap_or("");
+            if !["table", "tbody", "tr", "td", "div", "span"].contains(&name) {
+                return Err(format!("unexpected tag <{}>", tag));
+            }
+            if name == "tr" {
+                rows += 1;
+            }
+            if !tag.ends_with('/') {
+                stack.push(name);
+            }
+            continue;
+        }
+
+        // Text may only appear within the code cell of a row.
+        if !stack.contains(&"div") {
+            return Err(format!("text outside of a code cell: {:?}", rest));
+        }
+        let end = rest.find('<').unwrap_or(rest.len());
+        let mut chunk = &rest[..end];
+        rest = &rest[end..];
+        while !chunk.is_empty() {
+            if chunk.starts_with('&') {
+                let entity = ["&lt;", "&gt;", "&amp;", "&#39;", "&quot;"]
+                    .iter()
+                    .find(|e| chunk.starts_with(*e))
+                    .ok_or_else(|| format!("unescaped & in {:?}", chunk))?;
+                text.push_str(match *entity {
+                    "&lt;" => "<",
+                    "&gt;" => ">",
+                    "&amp;" => "&",
+                    "&#39;" => "'",
+                    _ => "\"",
+                });
+                chunk = &chunk[entity.len()..];
+            } else if chunk.starts_with('>') {
+                return Err(format!("unescaped > in {:?}", chunk));
+            } else {
+                let c = chunk.chars().next().unwrap();
+                text.push(c);
+                chunk = &chunk[c.len_utf8()..];
+            }
+        }
+    }
+
+    if !stack.is_empty() {
+        return Err(format!("unclosed tags {:?}", stack));
+    }
+    if text != code {
+        return Err(format!("text content {:?} differs from input", text));
+    }
+    let lines = LinesWithEndings::from(code).count();
+    if rows != lines {
+        return Err(format!("{} rows for {} lines", rows, lines));
+    }
+    Ok(())
+}
+
+fn check(ss: &SyntaxSet, extension: &str, code: &str, max_line_len: Option<usize>) {
+    let sr = ss
+        .find_syntax_by_extension(extension)
+        .unwrap_or_else(|| ss.find_syntax_plain_text());
+    let mapped = ["pygments", "hljs"]
+        .iter()
+        .map(|name| ClassNames::from_name(name, "").unwrap());
+    for names in CLASS_NAMES.iter().copied().chain(mapped) {
+        let html = ClassedTableGenerator::new(ss, sr, code, max_line_len, names).generate();
+        if let Err(err) = validate(&html, code) {
+            panic!(
+                "invalid HTML for {} ({:?}, max_line_len {:?}): {}\ninput: {:?}\noutput: {}",
+                extension, names, max_line_len, err, code, html
+            );
+        }
+    }
+}
+
+#[test]
+fn validator_rejects_broken_html() {
+    let code = "a<b\n";
+    let row = |cell: &str| {
+        format!(
+            "<table><tbody><tr><td class=\"line\" data-line=\"1\"/><td class=\"code\"><div>{}</div></td></tr></tbody></table>",
+            cell
+        )
+    };
+    assert!(validate(&row("a&lt;b\n"), code).is_ok());
+    assert!(validate(&row("<span class=\"x\">a&lt;b\n</span>"), code).is_ok());
+    assert!(validate(&row("a<b\n"), code).is_err());
+    assert!(validate(&row("a&lt;b\n</span>"), code).is_err());
+    assert!(validate(&row("<span class=\"x\">a&lt;b\n"), code).is_err());
+    assert!(validate(&row("a&lt;c\n"), code).is_err());
+    assert!(validate(&row("a & b\n"), "a & b\n").is_err());
+}
+
+#[test]
+fn random_inputs_produce_well_formed_html() {
+    let ss = SyntaxSet::load_defaults_newlines();
+    let seed = 0x5eed_1234_abcd_ef01;
+    let mut rng = Rng(seed);
+    for i in 0..300 {
+        let code = random_input(&mut rng);
+        let extension = EXTENSIONS[i % EXTENSIONS.len()];
+        let max_line_len = if i % 3 == 0 {
+            Some(rng.below(40))
+        } else {
+            None
+        };
+        check(&ss, extension, &code, max_line_len);
+    }
+}
+
+#[test]
+fn adversarial_inputs_produce_well_formed_html() {
+    let ss = SyntaxSet::load_defaults_newlines();
+    let inputs = &[
+        String::new(),
+        "\n\n\n".to_string(),
+        "no trailing newline".to_string(),
+        "\"unbalanced\n'quotes\n`everywhere\n".to_string(),
+        "/* unterminated comment\n\n".to_string(),
+        "a\r\nb\r\n\r\nc".to_string(),
+        "nul \0 bytes \0\n".to_string(),
+        "<div class=\"x\">&amp;</div><!-- <script>alert(1)</script>\n".to_string(),
+        "(".repeat(500) + &")".repeat(500),
+        "{[(".repeat(200) + "\n" + &")]}".repeat(200),
+        "\"".repeat(1001) + "\n" + &"'".repeat(1001),
+        "<".repeat(300) + &">".repeat(300) + "\n" + &"&".repeat(300),
+        "x\n".repeat(2000),
+    ];
+    for code in inputs {
+        for extension in EXTENSIONS {
+            check(&ss, extension, code, None);
+            check(&ss, extension, code, Some(10));
+        }
+    }
+}
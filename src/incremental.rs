@@ -0,0 +1,457 @@
+use crate::config::env_or;
+use crate::css_table::{self, ClassNames, ClassedTableGenerator};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::sync::Mutex;
+use syntect::{
+    parsing::{ParseState, Scope, ScopeStack, SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+lazy_static! {
+    // Documents which were recently highlighted with a document_id, so that
+    // subsequent edits to them only re-highlight the edited region.
+    static ref DOCUMENTS: Mutex<DocumentStore> = Mutex::new(DocumentStore::new(
+        env_or("INCREMENTAL_MAX_DOCUMENTS", 100),
+        env_or("INCREMENTAL_MAX_BYTES", 64 * 1024 * 1024),
+    ));
+
+    // The number of lines in between two parse state checkpoints. Smaller
+    // values make edits cheaper at the cost of memory.
+    static ref CHECKPOINT_INTERVAL: usize = env_or("CHECKPOINT_INTERVAL", 100).max(1);
+}
+
+/// LineRange is a half-open range of zero-based line numbers. In an edit
+/// request it describes the lines of the new version of the document that
+/// differ from the previous version; every line before `start` and every line
+/// from `end` onwards must be unchanged.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Checkpoint is the parser state right before highlighting a given line.
+struct Checkpoint {
+    line: usize,
+    parse_state: ParseState,
+    stack: ScopeStack,
+}
+
+/// Document is everything we remember about a highlighted document.
+struct Document {
+    syntax: String,
+    max_line_len: Option<usize>,
+    names: ClassNames,
+    line_hashes: Vec<u64>,
+    rows: Vec<String>,
+    // Sorted by line, the first checkpoint is always at line 0.
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Document {
+    // size estimates the memory the document takes up, which is dominated by
+    // its rows of HTML.
+    fn size(&self) -> usize {
+        let rows: usize = self.rows.iter().map(String::len).sum();
+        let stacks: usize = self
+            .checkpoints
+            .iter()
+            .map(|c| c.stack.as_slice().len() * mem::size_of::<Scope>())
+            .sum();
+        rows + stacks
+            + self.rows.len() * mem::size_of::<String>()
+            + self.line_hashes.len() * mem::size_of::<u64>()
+            + self.checkpoints.len() * mem::size_of::<Checkpoint>()
+    }
+}
+
+/// DocumentStore holds the most recently highlighted documents, bounded both
+/// by their number and by their total size.
+struct DocumentStore {
+    max_documents: usize,
+    max_bytes: usize,
+    bytes: usize,
+    tick: u64,
+    // Maps document ids to the tick at which they were last highlighted, their
+    // size and the document.
+    documents: HashMap<String, (u64, usize, Document)>,
+}
+
+impl DocumentStore {
+    fn new(max_documents: usize, max_bytes: usize) -> Self {
+        DocumentStore {
+            max_documents,
+            max_bytes,
+            bytes: 0,
+            tick: 0,
+            documents: HashMap::new(),
+        }
+    }
+
+    // take removes the document from the store while it is being updated, so
+    // that a panic while highlighting it can't leave a stale version behind.
+    fn take(&mut self, id: &str) -> Option<Document> {
+        let (_, size, doc) = self.documents.remove(id)?;
+        self.bytes -= size;
+        Some(doc)
+    }
+
+    fn insert(&mut self, id: String, doc: Document) {
+        let size = doc.size();
+        if self.max_documents == 0 || size > self.max_bytes {
+            return;
+        }
+        if let Some((_, old, _)) = self.documents.remove(&id) {
+            self.bytes -= old;
+        }
+        while self.documents.len() >= self.max_documents || self.bytes + size > self.max_bytes {
+            let oldest = self
+                .documents
+                .iter()
+                .min_by_key(|(_, (tick, _, _))| *tick)
+                .map(|(id, _)| id.clone())
+                .unwrap();
+            self.take(&oldest);
+        }
+        self.tick += 1;
+        self.bytes += size;
+        self.documents.insert(id, (self.tick, size, doc));
+    }
+}
+
+/// highlight highlights code as a CSS table, like `ClassedTableGenerator`
+/// does, and remembers checkpoints of the parse state under the given
+/// document id. When `changed` is given and the previous version of the
+/// document is known, highlighting resumes from the nearest checkpoint before
+/// the edit and stops as soon as the parse state converges with the previous
+/// version again, so that the work done is proportional to the edit rather
+/// than to the document.
+pub fn highlight(
+    ss: &SyntaxSet,
+    sr: &SyntaxReference,
+    document_id: &str,
+    code: &str,
+    max_line_len: Option<usize>,
+    changed: Option<LineRange>,
+    names: ClassNames,
+) -> String {
+    let previous = DOCUMENTS.lock().unwrap().take(document_id);
+    let doc = update(
+        ss,
+        sr,
+        previous,
+        code,
+        max_line_len,
+        changed,
+        names,
+        *CHECKPOINT_INTERVAL,
+    );
+    let html = css_table::table_from_rows(&doc.rows);
+    DOCUMENTS
+        .lock()
+        .unwrap()
+        .insert(document_id.to_string(), doc);
+    html
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update(
+    ss: &SyntaxSet,
+    sr: &SyntaxReference,
+    previous: Option<Document>,
+    code: &str,
+    max_line_len: Option<usize>,
+    changed: Option<LineRange>,
+    names: ClassNames,
+    interval: usize,
+) -> Document {
+    let lines: Vec<&str> = LinesWithEndings::from(code).collect();
+    let line_hashes: Vec<u64> = lines.iter().map(|line| hash_line(line)).collect();
+
+    let previous = previous.filter(|prev| {
+        prev.syntax == sr.name && prev.max_line_len == max_line_len && prev.names == names
+    });
+    if let (Some(prev), Some(changed)) = (previous, changed) {
+        if let Some(doc) = rehighlight(
+            ss,
+            prev,
+            &lines,
+            line_hashes.clone(),
+            changed,
+            names,
+            interval,
+        ) {
+            return doc;
+        }
+    }
+
+    // The document is new to us, or the edit doesn't match the version we
+    // know of, so highlight it from scratch.
+    let mut gen = ClassedTableGenerator::new(ss, sr, "", max_line_len, names);
+    let (parse_state, stack) = gen.state();
+    let mut checkpoints = vec![Checkpoint {
+        line: 0,
+        parse_state,
+        stack,
+    }];
+    let mut rows = Vec::with_capacity(lines.len());
+    highlight_lines(
+        &mut gen,
+        &lines,
+        0,
+        &mut rows,
+        &mut checkpoints,
+        None,
+        interval,
+    );
+    Document {
+        syntax: sr.name.clone(),
+        max_line_len,
+        names,
+        line_hashes,
+        rows,
+        checkpoints,
+    }
+}
+
+fn rehighlight(
+    ss: &SyntaxSet,
+    prev: Document,
+    lines: &[&str],
+    line_hashes: Vec<u64>,
+    changed: LineRange,
+    names: ClassNames,
+    interval: usize,
+) -> Option<Document> {
+    let Document {
+        syntax,
+        max_line_len,
+        names: _,
+        line_hashes: old_hashes,
+        mut rows,
+        mut checkpoints,
+    } = prev;
+
+    // Lines after the edit are the same in both versions, which tells us
+    // where the edit ended in the previous version.
+    if changed.start > changed.end || changed.end > lines.len() {
+        return None;
+    }
+    let unchanged_suffix = lines.len() - changed.end;
+    if unchanged_suffix > old_hashes.len() || old_hashes.len() - unchanged_suffix < changed.start {
+        return None;
+    }
+    let old_end = old_hashes.len() - unchanged_suffix;
+
+    // Don't trust the client blindly: highlighting a different version of the
+    // document than the one we have would silently produce wrong output.
+    if line_hashes[..changed.start] != old_hashes[..changed.start]
+        || line_hashes[changed.end..] != old_hashes[old_end..]
+    {
+        return None;
+    }
+
+    let resume = checkpoints.iter().rposition(|c| c.line <= changed.start)?;
+    let old_checkpoints = checkpoints.split_off(resume + 1);
+    let resume_line = checkpoints[resume].line;
+    let old_rows = rows.split_off(resume_line);
+
+    let Checkpoint {
+        parse_state, stack, ..
+    } = &checkpoints[resume];
+    let mut gen =
+        ClassedTableGenerator::resume(ss, parse_state.clone(), stack.clone(), max_line_len, names);
+    highlight_lines(
+        &mut gen,
+        lines,
+        resume_line,
+        &mut rows,
+        &mut checkpoints,
+        Some(Previous {
+            rows: old_rows,
+            rows_start: resume_line,
+            checkpoints: old_checkpoints,
+            changed_end: changed.end,
+            old_end,
+        }),
+        interval,
+    );
+    Some(Document {
+        syntax,
+        max_line_len,
+        names,
+        line_hashes,
+        rows,
+        checkpoints,
+    })
+}
+
+/// Previous is what remains of the previous version of a document after the
+/// checkpoint that highlighting resumed from.
+struct Previous {
+    rows: Vec<String>,
+    rows_start: usize,
+    checkpoints: Vec<Checkpoint>,
+    changed_end: usize,
+    old_end: usize,
+}
+
+// highlight_lines highlights lines from `start` onwards, recording a checkpoint
+// every `interval` lines. Once past the edit, whenever we reach a line for
+// which the previous version has a checkpoint with the same state, the rest
+// of the previous version is reused as is.
+fn highlight_lines(
+    gen: &mut ClassedTableGenerator,
+    lines: &[&str],
+    start: usize,
+    rows: &mut Vec<String>,
+    checkpoints: &mut Vec<Checkpoint>,
+    mut previous: Option<Previous>,
+    interval: usize,
+) {
+    let mut next_old = 0;
+    for (i, line) in lines.iter().enumerate().skip(start) {
+        if let Some(prev) = &mut previous {
+            if i >= prev.changed_end {
+                let old_i = i - prev.changed_end + prev.old_end;
+                while next_old < prev.checkpoints.len() && prev.checkpoints[next_old].line < old_i {
+                    next_old += 1;
+                }
+                let converged = prev.checkpoints.get(next_old).map_or(false, |c| {
+                    c.line == old_i && gen.is_at_state(&c.parse_state, &c.stack)
+                });
+                if converged {
+                    rows.extend(prev.rows.drain(old_i - prev.rows_start..));
+                    let (changed_end, old_end) = (prev.changed_end, prev.old_end);
+                    checkpoints.extend(prev.checkpoints.drain(next_old..).map(|c| Checkpoint {
+                        line: c.line - old_end + changed_end,
+                        ..c
+                    }));
+                    return;
+                }
+            }
+        }
+
+        if i >= checkpoints.last().map_or(0, |c| c.line) + interval {
+            let (parse_state, stack) = gen.state();
+            checkpoints.push(Checkpoint {
+                line: i,
+                parse_state,
+                stack,
+            });
+        }
+        rows.push(gen.highlight_line(line));
+    }
+}
+
+fn hash_line(line: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{update, Document, DocumentStore, LineRange};
+    use crate::css_table::{self, ClassNames, ClassedTableGenerator};
+    use syntect::parsing::SyntaxSet;
+
+    const STYLE: ClassNames = ClassNames::Full { prefix: "hl-" };
+
+    fn full(ss: &SyntaxSet, code: &str) -> String {
+        let sr = ss.find_syntax_by_extension("go").unwrap();
+        ClassedTableGenerator::new(ss, sr, code, None, STYLE).generate()
+    }
+
+    fn edit(ss: &SyntaxSet, prev: Document, code: &str, changed: LineRange) -> Document {
+        let sr = ss.find_syntax_by_extension("go").unwrap();
+        update(ss, sr, Some(prev), code, None, Some(changed), STYLE, 2)
+    }
+
+    fn go_file(body: &str) -> String {
+        let mut code = String::from("package main\n\nimport \"fmt\"\n\n");
+        for i in 0..20 {
+            code.push_str(&format!("func f{}() {{\n\tfmt.Println({})\n}}\n", i, i));
+        }
+        code.push_str(body);
+        code
+    }
+
+    #[test]
+    fn edit_matches_full_highlight() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let sr = ss.find_syntax_by_extension("go").unwrap();
+        let before = go_file("");
+        let doc = update(&ss, sr, None, &before, None, None, STYLE, 2);
+        assert_eq!(css_table::table_from_rows(&doc.rows), full(&ss, &before));
+
+        // Replace line 5 with three lines, one of which opens a string.
+        let mut lines: Vec<&str> = before.lines().collect();
+        lines.splice(5..6, vec!["\tx := 1", "\ty := `", "\t`"]);
+        let after = lines.join("\n") + "\n";
+        let doc = edit(&ss, doc, &after, LineRange { start: 5, end: 8 });
+        assert_eq!(css_table::table_from_rows(&doc.rows), full(&ss, &after));
+        assert!(doc.checkpoints.windows(2).all(|w| w[0].line < w[1].line));
+
+        // Delete the line which closes the raw string, so that the parse state
+        // never converges again.
+        let mut lines: Vec<&str> = after.lines().collect();
+        lines.remove(7);
+        let after_delete = lines.join("\n") + "\n";
+        let doc = edit(&ss, doc, &after_delete, LineRange { start: 7, end: 7 });
+        assert_eq!(
+            css_table::table_from_rows(&doc.rows),
+            full(&ss, &after_delete)
+        );
+    }
+
+    #[test]
+    fn mismatched_edit_falls_back_to_full_highlight() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let sr = ss.find_syntax_by_extension("go").unwrap();
+        let before = go_file("");
+        let doc = update(&ss, sr, None, &before, None, None, STYLE, 2);
+
+        // The client claims only the last line changed, but the first did too.
+        let after = before.replacen("package main", "package other", 1) + "// end\n";
+        let end = after.lines().count();
+        let doc = edit(
+            &ss,
+            doc,
+            &after,
+            LineRange {
+                start: end - 1,
+                end,
+            },
+        );
+        assert_eq!(css_table::table_from_rows(&doc.rows), full(&ss, &after));
+    }
+
+    #[test]
+    fn store_evicts_least_recently_used_documents_over_budget() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let sr = ss.find_syntax_by_extension("go").unwrap();
+        let doc = || update(&ss, sr, None, &go_file(""), None, None, STYLE, 2);
+        let size = doc().size();
+
+        let mut store = DocumentStore::new(100, 2 * size);
+        store.insert("a".to_string(), doc());
+        store.insert("b".to_string(), doc());
+        // Highlighting a again makes b the least recently used.
+        let a = store.take("a").unwrap();
+        store.insert("a".to_string(), a);
+        store.insert("c".to_string(), doc());
+        assert!(store.take("b").is_none());
+        assert!(store.take("a").is_some());
+        assert!(store.take("c").is_some());
+        assert_eq!(store.bytes, 0);
+
+        // Documents over the budget by themselves are not kept at all.
+        let mut store = DocumentStore::new(100, size - 1);
+        store.insert("a".to_string(), doc());
+        assert!(store.take("a").is_none());
+    }
+}
@@ -0,0 +1,82 @@
+use crate::config::env_or;
+
+lazy_static! {
+    // Limits on the size of the code to highlight, so that a single huge
+    // input cannot tie up a worker for minutes. 0 disables a limit.
+    pub static ref MAX_CODE_BYTES: usize = env_or("MAX_CODE_BYTES", 4 * 1024 * 1024);
+    static ref MAX_LINES: usize = env_or("MAX_LINES", 100_000);
+    // Off by default, as the line_length_limit of the query already renders
+    // long lines (e.g. of minified code) as plain text, but not the rest.
+    static ref MAX_LINE_BYTES: usize = env_or("MAX_LINE_BYTES", 0);
+
+    // Whether code over the limits is rendered as plain text (the default),
+    // or rejected with a too_large error.
+    pub static ref TOO_LARGE_AS_PLAINTEXT: bool = env_or("TOO_LARGE_AS_PLAINTEXT", true);
+}
+
+/// exceeded returns which limit the code exceeds, if any.
+pub fn exceeded(code: &str) -> Option<String> {
+    check(code, *MAX_CODE_BYTES, *MAX_LINES, *MAX_LINE_BYTES)
+}
+
+/// exceeded_size returns why the code exceeds MAX_CODE_BYTES, if it does. It
+/// is the only limit of documents such as notebooks, whose lines don't count,
+/// as they contain e.g. images encoded on a single line.
+pub fn exceeded_size(code: &str) -> Option<String> {
+    check(code, *MAX_CODE_BYTES, 0, 0)
+}
+
+fn check(code: &str, max_bytes: usize, max_lines: usize, max_line_bytes: usize) -> Option<String> {
+    if max_bytes > 0 && code.len() > max_bytes {
+        return Some(format!(
+            "code is {} bytes, more than the limit of {}",
+            code.len(),
+            max_bytes
+        ));
+    }
+    if max_lines == 0 && max_line_bytes == 0 {
+        return None;
+    }
+    let mut lines = 0;
+    for line in code.lines() {
+        lines += 1;
+        if max_line_bytes > 0 && line.len() > max_line_bytes {
+            return Some(format!(
+                "line {} is {} bytes, more than the limit of {}",
+                lines,
+                line.len(),
+                max_line_bytes
+            ));
+        }
+    }
+    if max_lines > 0 && lines > max_lines {
+        return Some(format!(
+            "code has {} lines, more than the limit of {}",
+            lines, max_lines
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check;
+
+    #[test]
+    fn limits() {
+        assert_eq!(check("a\nbb\nccc\n", 0, 0, 0), None);
+        assert_eq!(check("a\nbb\nccc\n", 9, 3, 3), None);
+        assert_eq!(
+            check("a\nbb\nccc\n", 8, 0, 0).unwrap(),
+            "code is 9 bytes, more than the limit of 8"
+        );
+        assert_eq!(
+            check("a\nbb\nccc\n", 0, 2, 0).unwrap(),
+            "code has 3 lines, more than the limit of 2"
+        );
+        assert_eq!(
+            check("a\nbb\nccc\n", 0, 0, 1).unwrap(),
+            "line 2 is 2 bytes, more than the limit of 1"
+        );
+    }
+}
@@ -12,18 +12,46 @@ extern crate serde_derive;
 extern crate serde_json;
 extern crate syntect;
 
+use rocket::response::status;
 use rocket_contrib::json::{Json, JsonValue};
 use std::env;
 use std::panic;
 use std::path::Path;
+use std::process;
 use syntect::{
     highlighting::ThemeSet,
-    html::{highlighted_html_for_string, ClassStyle},
-    parsing::SyntaxSet,
+    parsing::{SyntaxReference, SyntaxSet},
 };
 
+mod bench;
+mod breaker;
+mod cache;
+mod class_map;
+mod classifier;
+mod code_blocks;
+mod config;
 mod css_table;
-use css_table::ClassedTableGenerator;
+mod engine;
+mod hints;
+#[cfg(test)]
+mod html_validation;
+mod incremental;
+mod limits;
+mod notebook;
+mod occurrences;
+mod panics;
+mod readiness;
+mod request_log;
+mod semantic_tokens;
+#[cfg(test)]
+mod snapshot_tests;
+mod supervisor;
+mod syntax_test;
+mod themed;
+#[cfg(feature = "tree-sitter-backend")]
+mod treesitter;
+use css_table::{ClassNames, ClassedTableGenerator};
+use engine::Engine;
 
 thread_local! {
     static SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
@@ -33,7 +61,7 @@ lazy_static! {
     static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 struct Query {
     // Deprecated field with a default empty string value, kept for backwards
     // compatability with old clients.
@@ -49,13 +77,53 @@ struct Query {
     #[serde(default)]
     css: bool,
 
-    // line_length_limit is ignored if css is false
+    // classes (CSS mode only) selects the class names of the spans, see
+    // css_table::ClassNames::from_name. Defaults to every atom of every scope.
+    #[serde(default)]
+    classes: String,
+
+    // Lines longer than line_length_limit are not highlighted, if it is set.
     line_length_limit: Option<usize>,
 
     // theme is ignored if css is true
     theme: String,
 
+    // If table is set (themed mode only), the code is returned as the same HTML
+    // table as in CSS mode, but with inline styles and line numbers.
+    #[serde(default)]
+    table: bool,
+
+    // engine selects the parser, "syntect" or "tree-sitter", see
+    // engine::Engine::select. Defaults to the one configured for the language.
+    #[serde(default)]
+    engine: String,
+
+    // If semantic_tokens is set, the code is returned as LSP semantic tokens
+    // instead of HTML, and css, classes, theme and table are ignored.
+    #[serde(default)]
+    semantic_tokens: bool,
+
+    // If occurrences is set to "json" or "protobuf", the code is returned as
+    // SCIP syntax highlighting occurrences in that format, and css, classes,
+    // theme and table are ignored.
+    #[serde(default)]
+    occurrences: String,
+
     code: String,
+
+    // If document_id is set (css mode only), checkpoints of the parse state are
+    // kept for the document so that later edits to it can be re-highlighted
+    // incrementally.
+    document_id: Option<String>,
+
+    // changed_lines is the range of lines which changed since the previous
+    // request for the same document_id, if any.
+    changed_lines: Option<incremental::LineRange>,
+
+    // If notebook is set, the code is a Jupyter notebook whose cells are
+    // highlighted separately, see highlight_notebook.
+    #[serde(default)]
+    notebook: bool,
 }
 
 #[post("/", format = "application/json", data = "<q>")]
@@ -64,96 +132,416 @@ fn index(q: Json<Query>) -> JsonValue {
     // and instead Syntect would return Result types when failures occur. This
     // will require some non-trivial work upstream:
     // https://github.com/trishume/syntect/issues/98
-    let result = panic::catch_unwind(|| highlight(q.into_inner()));
+    let q = q.into_inner();
+    let entry = request_log::Entry::new(&q);
+    let result = panic::catch_unwind(|| highlight(q));
     match result {
-        Ok(v) => v,
-        Err(_) => json!({"error": "panic while highlighting code", "code": "panic"}),
+        Ok(v) => {
+            entry.finish(Ok(&v));
+            v
+        }
+        Err(payload) => {
+            let panic = panics::take(&*payload);
+            entry.finish(Err(&panic));
+            if *panics::PANIC_DETAIL {
+                json!({"error": "panic while highlighting code", "code": "panic", "detail": panic})
+            } else {
+                json!({"error": "panic while highlighting code", "code": "panic"})
+            }
+        }
     }
 }
 
 fn highlight(q: Query) -> JsonValue {
     SYNTAX_SET.with(|syntax_set| {
-        // Determine syntax definition by extension.
-        let mut is_plaintext = false;
-        let syntax_def = if q.filepath == "" {
-            // Legacy codepath, kept for backwards-compatability with old clients.
-            match syntax_set.find_syntax_by_extension(&q.extension) {
-                Some(v) => v,
-                None =>
-                // Fall back: Determine syntax definition by first line.
-                {
-                    match syntax_set.find_syntax_by_first_line(&q.code) {
-                        Some(v) => v,
-                        None => return json!({"error": "invalid extension"}),
-                    }
-                }
+        if q.notebook {
+            return highlight_notebook(syntax_set, &q);
+        }
+        let (syntax_def, is_plaintext, confidence) = match detect_syntax(syntax_set, &q) {
+            Some(v) => v,
+            None => return json!({"error": "invalid extension"}),
+        };
+        request_log::set_syntax(&syntax_def.name);
+        let mut result = highlight_checked(syntax_set, syntax_def, is_plaintext, &q);
+        if let (Some(confidence), Some(result)) = (confidence, result.as_object_mut()) {
+            if !result.contains_key("error") {
+                result.insert("confidence".to_string(), confidence.into());
             }
-        } else {
-            // Split the input path ("foo/myfile.go") into file name
-            // ("myfile.go") and extension ("go").
-            let path = Path::new(&q.filepath);
-            let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
-            let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("");
-
-            // To determine the syntax definition, we must first check using the
-            // filename as some syntaxes match an "extension" that is actually a
-            // whole file name (e.g. "Dockerfile" or "CMakeLists.txt"); see e.g. https://github.com/trishume/syntect/pull/170
-            //
-            // After that, if we do not find any syntax, we can actually check by
-            // extension and lastly via the first line of the code.
-
-            // First try to find a syntax whose "extension" matches our file
-            // name. This is done due to some syntaxes matching an "extension"
-            // that is actually a whole file name (e.g. "Dockerfile" or "CMakeLists.txt")
-            // see https://github.com/trishume/syntect/pull/170
-            syntax_set
-                .find_syntax_by_extension(file_name)
-                .or_else(|| syntax_set.find_syntax_by_extension(extension))
-                .or_else(|| syntax_set.find_syntax_by_first_line(&q.code))
-                .unwrap_or_else(|| {
-                    is_plaintext = true;
-                    syntax_set.find_syntax_plain_text()
-                })
+        }
+        result
+    })
+}
+
+/// highlight_checked highlights the query's code with the given syntax, or as
+/// plain text if the code exceeds the limits or the syntax's circuit breaker
+/// is tripped.
+fn highlight_checked(
+    syntax_set: &SyntaxSet,
+    syntax_def: &SyntaxReference,
+    is_plaintext: bool,
+    q: &Query,
+) -> JsonValue {
+    if let Some(reason) = limits::exceeded(&q.code) {
+        if !*limits::TOO_LARGE_AS_PLAINTEXT {
+            return json!({"error": reason, "code": "too_large"});
+        }
+        return highlight_as_plaintext(syntax_set, q, reason);
+    }
+
+    // Languages whose grammar keeps failing are served as plain text until
+    // their circuit breaker cools down.
+    if let Some(reason) = breaker::tripped(&syntax_def.name) {
+        return highlight_as_plaintext(syntax_set, q, reason);
+    }
+    let _guard = breaker::Guard::new(&syntax_def.name);
+    highlight_with(syntax_set, syntax_def, is_plaintext, q)
+}
+
+/// highlight_notebook highlights the cells of the Jupyter notebook in the
+/// query's code: code cells with the kernel's language, Markdown cells as
+/// Markdown and raw cells as plain text. The data of the response is a list
+/// of the cells, each with the fields of the response for its source alone,
+/// its type, language and the line of the notebook each of its lines is on.
+/// Limits and circuit breakers apply to each cell separately, so that the
+/// outputs stored in the notebook don't count, but the whole notebook must be
+/// within MAX_CODE_BYTES too, as it is parsed first.
+fn highlight_notebook(syntax_set: &SyntaxSet, q: &Query) -> JsonValue {
+    if let Some(reason) = limits::exceeded_size(&q.code) {
+        if !*limits::TOO_LARGE_AS_PLAINTEXT {
+            return json!({"error": reason, "code": "too_large"});
+        }
+        return highlight_as_plaintext(syntax_set, q, reason);
+    }
+    let notebook = match notebook::parse(&q.code) {
+        Ok(notebook) => notebook,
+        Err(_) => return json!({"error": "invalid notebook", "code": "invalid_notebook"}),
+    };
+    // Some syntaxes have an empty extension, so the kernel's language must not
+    // be looked up if the notebook doesn't name it.
+    let kernel_syntax = Some(notebook.language.as_str())
+        .filter(|language| !language.is_empty())
+        .and_then(|language| syntax_set.find_syntax_by_token(language))
+        .or_else(|| {
+            Some(notebook.file_extension.as_str())
+                .filter(|extension| !extension.is_empty())
+                .and_then(|extension| syntax_set.find_syntax_by_extension(extension))
+        });
+    if let Some(syntax) = kernel_syntax {
+        request_log::set_syntax(&syntax.name);
+    }
+
+    let mut cells = Vec::with_capacity(notebook.cells.len());
+    for cell in notebook.cells {
+        let syntax_def = match cell.cell_type.as_str() {
+            "code" => kernel_syntax,
+            "markdown" => syntax_set.find_syntax_by_name("Markdown"),
+            _ => None,
+        };
+        let (syntax_def, is_plaintext) = match syntax_def {
+            Some(syntax_def) => (syntax_def, false),
+            None => (
+                syntax_set.find_syntax_plain_text(),
+                cell.cell_type == "code",
+            ),
+        };
+        let cell_query = Query {
+            filepath: q.filepath.clone(),
+            css: q.css,
+            classes: q.classes.clone(),
+            line_length_limit: q.line_length_limit,
+            theme: q.theme.clone(),
+            table: q.table,
+            engine: q.engine.clone(),
+            semantic_tokens: q.semantic_tokens,
+            occurrences: q.occurrences.clone(),
+            code: cell.source,
+            ..Query::default()
         };
+        let mut result = highlight_checked(syntax_set, syntax_def, is_plaintext, &cell_query);
+        if result.get("error").is_some() {
+            return result;
+        }
+        if let Some(result) = result.as_object_mut() {
+            result.insert("cell_type".to_string(), cell.cell_type.into());
+            result.insert("language".to_string(), syntax_def.name.clone().into());
+            result.insert("lines".to_string(), json!(cell.lines).0);
+        }
+        cells.push(result.0);
+    }
+    json!({
+        "data": cells,
+        "plaintext": kernel_syntax.is_none(),
+    })
+}
+
+/// highlight_as_plaintext renders the query's code as plain text, giving the
+/// reason why it wasn't highlighted in the response.
+fn highlight_as_plaintext(syntax_set: &SyntaxSet, q: &Query, reason: String) -> JsonValue {
+    let plaintext = syntax_set.find_syntax_plain_text();
+    let mut result = highlight_with(syntax_set, plaintext, true, q);
+    if let Some(result) = result.as_object_mut() {
+        result.insert("reason".to_string(), reason.into());
+    }
+    result
+}
+
+/// highlight_with highlights the query's code with the given syntax.
+fn highlight_with(
+    syntax_set: &SyntaxSet,
+    syntax_def: &SyntaxReference,
+    is_plaintext: bool,
+    q: &Query,
+) -> JsonValue {
+    let engine = match Engine::select(&q.engine, syntax_def) {
+        Some(engine) => engine,
+        None => return json!({"error": "invalid engine", "code": "invalid_engine"}),
+    };
 
-        if q.css {
-            let output = ClassedTableGenerator::new(
+    if !q.occurrences.is_empty() {
+        let format = match occurrences::Format::from_name(&q.occurrences) {
+            Some(format) => format,
+            None => {
+                return json!({"error": "invalid occurrences format", "code": "invalid_occurrences"})
+            }
+        };
+        let key = cache::Key::new(
+            &q.code,
+            &syntax_def.name,
+            is_plaintext,
+            "",
+            &format!("occurrences {:?} {:?} {}", format, engine, q.filepath),
+            q.line_length_limit,
+        );
+        if let Some(cached) = cache::get(&key) {
+            return cached;
+        }
+        let parsed_lines = engine::parse(
+            engine,
+            &q.code,
+            &syntax_set,
+            &syntax_def,
+            q.line_length_limit,
+        );
+        let occurrences = occurrences::occurrences(&q.code, parsed_lines);
+        let result = json!({
+            "data": occurrences::serialize(format, &q.filepath, &syntax_def.name, &occurrences),
+            "plaintext": is_plaintext,
+        });
+        cache::insert(key, &result);
+        return result;
+    }
+
+    if q.semantic_tokens {
+        let key = cache::Key::new(
+            &q.code,
+            &syntax_def.name,
+            is_plaintext,
+            "",
+            &format!("semantic_tokens {:?}", engine),
+            q.line_length_limit,
+        );
+        if let Some(cached) = cache::get(&key) {
+            return cached;
+        }
+        let parsed_lines = engine::parse(
+            engine,
+            &q.code,
+            &syntax_set,
+            &syntax_def,
+            q.line_length_limit,
+        );
+        let result = json!({
+            "data": semantic_tokens::encode(&q.code, parsed_lines),
+            "legend": semantic_tokens::legend(),
+            "plaintext": is_plaintext,
+        });
+        cache::insert(key, &result);
+        return result;
+    }
+
+    let names = ClassNames::from_name(&q.classes, "hl-");
+    if q.css && names.is_none() {
+        return json!({"error": "invalid classes", "code": "invalid_classes"});
+    }
+    let names = names.unwrap_or(ClassNames::Full { prefix: "hl-" });
+
+    // Code blocks in markup are highlighted by parsing the whole document, so
+    // markup is always highlighted from scratch.
+    if q.css && !code_blocks::has_code_blocks(syntax_def) {
+        if let Some(document_id) = &q.document_id {
+            // Documents being edited are never served from the result
+            // cache, as we must keep track of every version of them.
+            let output = incremental::highlight(
                 &syntax_set,
                 &syntax_def,
+                document_id,
                 &q.code,
                 q.line_length_limit,
-                ClassStyle::SpacedPrefixed { prefix: "hl-" },
-            )
-            .generate();
-
-            json!({
+                q.changed_lines,
+                names,
+            );
+            return json!({
                 "data": output,
                 "plaintext": is_plaintext,
-            })
-        } else {
-            // TODO(slimsag): return the theme's background color (and other info??) to caller?
-            // https://github.com/trishume/syntect/blob/c8b47758a3872d478c7fc740782cd468b2c0a96b/examples/synhtml.rs#L24
-
-            // Determine theme to use.
-            //
-            // TODO(slimsag): We could let the query specify the theme file's actual
-            // bytes? e.g. via `load_from_reader`.
-            let theme = match THEME_SET.themes.get(&q.theme) {
-                Some(v) => v,
-                None => return json!({"error": "invalid theme", "code": "invalid_theme"}),
-            };
-
-            json!({
-                "data": highlighted_html_for_string(&q.code, &syntax_set, &syntax_def, theme),
-                "plaintext": is_plaintext,
-            })
+            });
         }
-    })
+    }
+
+    // The theme is ignored in CSS mode, so it must not affect the cache key.
+    let key_theme = if q.css { "" } else { q.theme.as_str() };
+    let mode = match (q.css, q.table) {
+        (true, _) => format!("css {:?} {:?}", names, engine),
+        (false, false) => "themed".to_string(),
+        (false, true) => "themed-table".to_string(),
+    };
+    let key = cache::Key::new(
+        &q.code,
+        &syntax_def.name,
+        is_plaintext,
+        key_theme,
+        &mode,
+        q.line_length_limit,
+    );
+    if let Some(cached) = cache::get(&key) {
+        return cached;
+    }
+
+    if q.css {
+        let parsed_lines = engine::parse_upfront(
+            engine,
+            &q.code,
+            &syntax_set,
+            &syntax_def,
+            q.line_length_limit,
+        );
+        let output = match parsed_lines {
+            Some(parsed_lines) => css_table::table_for_parsed_lines(&q.code, &parsed_lines, names),
+            None => ClassedTableGenerator::new(
+                &syntax_set,
+                &syntax_def,
+                &q.code,
+                q.line_length_limit,
+                names,
+            )
+            .generate(),
+        };
+
+        let result = json!({
+            "data": output,
+            "plaintext": is_plaintext,
+        });
+        cache::insert(key, &result);
+        result
+    } else {
+        // TODO(slimsag): return the theme's background color (and other info??) to caller?
+        // https://github.com/trishume/syntect/blob/c8b47758a3872d478c7fc740782cd468b2c0a96b/examples/synhtml.rs#L24
+
+        // Determine theme to use.
+        //
+        // TODO(slimsag): We could let the query specify the theme file's actual
+        // bytes? e.g. via `load_from_reader`.
+        let theme = match THEME_SET.themes.get(&q.theme) {
+            Some(v) => v,
+            None => return json!({"error": "invalid theme", "code": "invalid_theme"}),
+        };
+
+        let render = if q.table {
+            themed::highlighted_table_for_string
+        } else {
+            themed::highlighted_html_for_string
+        };
+        let result = json!({
+            "data": render(&q.code, &syntax_set, &syntax_def, theme, q.line_length_limit),
+            "plaintext": is_plaintext,
+        });
+        cache::insert(key, &result);
+        result
+    }
+}
+
+/// detect_syntax determines the syntax definition to highlight the query's code
+/// with, whether we fell back to plain text because none could be found, and
+/// the confidence in it if its extension is shared by several syntaxes, see
+/// classifier::classify.
+fn detect_syntax<'a>(
+    syntax_set: &'a SyntaxSet,
+    q: &Query,
+) -> Option<(&'a SyntaxReference, bool, Option<f64>)> {
+    let mut confidence = None;
+    // by_extension picks among the syntaxes with the extension by the code.
+    let mut by_extension =
+        |extension: &str| match classifier::classify(syntax_set, extension, &q.code) {
+            Some((syntax, c)) => {
+                // Two decimals are plenty, and keep responses small.
+                confidence = Some((c * 100.0).round() / 100.0);
+                Some(syntax)
+            }
+            None => syntax_set.find_syntax_by_extension(extension),
+        };
+
+    // Determine syntax definition by extension.
+    if q.filepath == "" {
+        // Legacy codepath, kept for backwards-compatability with old clients.
+        return by_extension(&q.extension)
+            // Fall back: Determine syntax definition by language hints, then
+            // by first line.
+            .or_else(|| hints::detect(syntax_set, &q.code))
+            .or_else(|| syntax_set.find_syntax_by_first_line(&q.code))
+            .map(|v| (v, false, confidence));
+    }
+
+    // Split the input path ("foo/myfile.go") into file name
+    // ("myfile.go") and extension ("go").
+    let path = Path::new(&q.filepath);
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("");
+
+    // To determine the syntax definition, we must first check using the
+    // filename as some syntaxes match an "extension" that is actually a
+    // whole file name (e.g. "Dockerfile" or "CMakeLists.txt"); see e.g. https://github.com/trishume/syntect/pull/170
+    //
+    // After that, if we do not find any syntax, we can actually check by
+    // extension, via the language hints in the code and lastly via the first
+    // line of the code.
+
+    // First try to find a syntax whose "extension" matches our file
+    // name. This is done due to some syntaxes matching an "extension"
+    // that is actually a whole file name (e.g. "Dockerfile" or "CMakeLists.txt")
+    // see https://github.com/trishume/syntect/pull/170
+    let syntax_def = syntax_set
+        .find_syntax_by_extension(file_name)
+        .or_else(|| by_extension(extension))
+        // Editor modelines and the interpreter of the shebang name the
+        // language, unlike the first-line patterns of the grammars, which
+        // e.g. take any `#!/bin/sh` script for a shell script.
+        .or_else(|| hints::detect(syntax_set, &q.code))
+        .or_else(|| syntax_set.find_syntax_by_first_line(&q.code));
+    match syntax_def {
+        Some(v) => Some((v, false, confidence)),
+        None => Some((syntax_set.find_syntax_plain_text(), true, None)),
+    }
+}
+
+#[get("/cache")]
+fn cache_stats() -> JsonValue {
+    cache::stats()
 }
 
 #[get("/health")]
-fn health() -> &'static str {
-    "OK"
+fn health() -> String {
+    // Tripped circuit breakers don't make the server unhealthy, but are
+    // reported so that failing grammars get noticed.
+    let mut status = "OK".to_string();
+    for reason in breaker::tripped_reasons() {
+        status.push_str("\ncircuit breaker tripped: ");
+        status.push_str(&reason);
+    }
+    status
+}
+
+#[get("/ready")]
+fn ready() -> status::Custom<JsonValue> {
+    readiness::ready()
 }
 
 #[catch(404)]
@@ -183,6 +571,16 @@ fn list_features() {
 
 #[launch]
 fn rocket() -> rocket::Rocket {
+    // Subcommands run instead of the server. Other arguments are ignored, as
+    // the server never took any, and existing deployments may pass some.
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("bench") => process::exit(bench::run(&args[2..])),
+        Some("supervise") => process::exit(supervisor::run(&args[2..])),
+        Some("syntax-test") => process::exit(syntax_test::run(&args[2..])),
+        _ => {}
+    }
+
     // Only list features if QUIET != "true"
     match env::var("QUIET") {
         Ok(v) => {
@@ -193,7 +591,9 @@ fn rocket() -> rocket::Rocket {
         Err(_) => list_features(),
     };
 
+    panics::install();
+
     rocket::ignite()
-        .mount("/", routes![index, health])
+        .mount("/", routes![index, health, ready, cache_stats])
         .register(catchers![not_found])
 }
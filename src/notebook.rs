@@ -0,0 +1,257 @@
+use serde_json::Value;
+use syntect::util::LinesWithEndings;
+
+/// Notebook is the highlightable content of a Jupyter notebook.
+#[derive(Debug)]
+pub struct Notebook {
+    /// The name of the kernel's language, e.g. `python`, or "" if unknown.
+    pub language: String,
+    /// The file extension of the kernel's language, e.g. `py`, or "".
+    pub file_extension: String,
+    pub cells: Vec<Cell>,
+}
+
+/// Cell is a cell of a notebook. Outputs are not highlighted, so they are
+/// left out.
+#[derive(Debug)]
+pub struct Cell {
+    /// `code`, `markdown` or `raw`.
+    pub cell_type: String,
+    pub source: String,
+    /// The zero-based line of the notebook file each line of the source is
+    /// on, or None if it could not be found.
+    pub lines: Vec<Option<usize>>,
+}
+
+/// parse returns the cells of the notebook, given the JSON of an nbformat 4
+/// `.ipynb` file.
+pub fn parse(s: &str) -> Result<Notebook, String> {
+    let notebook: Value = serde_json::from_str(s).map_err(|err| err.to_string())?;
+    let cells = notebook["cells"]
+        .as_array()
+        .ok_or_else(|| "notebook has no cells".to_string())?;
+
+    // language_info is written by the kernel, kernelspec by the frontend when
+    // the notebook is created, so the former is more accurate.
+    let metadata = &notebook["metadata"];
+    let language = metadata["language_info"]["name"]
+        .as_str()
+        .or_else(|| metadata["kernelspec"]["language"].as_str())
+        .unwrap_or("");
+    let file_extension = metadata["language_info"]["file_extension"]
+        .as_str()
+        .unwrap_or("")
+        .trim_start_matches('.');
+
+    let mut finder = LineFinder { s, pos: 0, line: 0 };
+    let cells = cells
+        .iter()
+        .map(|cell| {
+            let cell_type = cell["cell_type"].as_str().unwrap_or("raw").to_string();
+            // The source is either a single string or, as nbformat writes it,
+            // an array of strings which are usually one line each.
+            let parts: Vec<&str> = match &cell["source"] {
+                Value::String(source) => vec![source.as_str()],
+                Value::Array(parts) => parts.iter().filter_map(Value::as_str).collect(),
+                _ => vec![],
+            };
+            finder.skip_to_source();
+            let mut lines = Vec::new();
+            for part in &parts {
+                let line = finder.find(part);
+                lines.extend(LinesWithEndings::from(part).map(|_| line));
+            }
+            Cell {
+                cell_type,
+                source: parts.concat(),
+                lines,
+            }
+        })
+        .collect();
+
+    Ok(Notebook {
+        language: language.to_string(),
+        file_extension: file_extension.to_string(),
+        cells,
+    })
+}
+
+// LineFinder finds the lines of the notebook file which the source strings of
+// its cells are on, by searching for their JSON encoding in the file, in
+// order. A string containing escaped newlines is on a single line of the file.
+struct LineFinder<'a> {
+    s: &'a str,
+    // The position searching continues from, and its line.
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> LineFinder<'a> {
+    // skip_to_source advances past the next `"source"` key, so that strings
+    // in the outputs and metadata of a cell are not mistaken for its source.
+    fn skip_to_source(&mut self) {
+        let mut from = self.pos;
+        while let Some(i) = self.s[from..].find("\"source\"") {
+            let end = from + i + "\"source\"".len();
+            if self.s[end..].trim_start().starts_with(':') {
+                self.advance(end);
+                return;
+            }
+            from = end;
+        }
+    }
+
+    // find returns the line of the next occurrence of the string, if any.
+    fn find(&mut self, part: &str) -> Option<usize> {
+        if part.is_empty() {
+            return None;
+        }
+        let needle = serde_json::to_string(part).ok()?;
+        let i = self.s[self.pos..].find(&needle)?;
+        self.advance(self.pos + i);
+        let line = self.line;
+        self.advance(self.pos + needle.len());
+        Some(line)
+    }
+
+    fn advance(&mut self, pos: usize) {
+        self.line += self.s[self.pos..pos].matches('\n').count();
+        self.pos = pos;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::{highlight, limits, Query};
+
+    // NOTEBOOK is formatted the way Jupyter writes notebooks.
+    const NOTEBOOK: &str = r##"{
+ "cells": [
+  {
+   "cell_type": "markdown",
+   "metadata": {},
+   "source": [
+    "# Title\n",
+    "\n",
+    "Some *text*."
+   ]
+  },
+  {
+   "cell_type": "code",
+   "execution_count": 1,
+   "metadata": {},
+   "outputs": [
+    {
+     "name": "stdout",
+     "output_type": "stream",
+     "text": [
+      "def f():\n"
+     ]
+    }
+   ],
+   "source": [
+    "def f():\n",
+    "    return \"hi\"\n"
+   ]
+  },
+  {
+   "cell_type": "raw",
+   "metadata": {},
+   "source": "a\nb"
+  }
+ ],
+ "metadata": {
+  "kernelspec": {
+   "display_name": "Python 3",
+   "language": "python",
+   "name": "python3"
+  },
+  "language_info": {
+   "file_extension": ".py",
+   "name": "python"
+  }
+ },
+ "nbformat": 4,
+ "nbformat_minor": 4
+}
+"##;
+
+    #[test]
+    fn parses_cells_and_their_lines() {
+        let notebook = parse(NOTEBOOK).unwrap();
+        assert_eq!(notebook.language, "python");
+        assert_eq!(notebook.file_extension, "py");
+        let cells: Vec<(&str, &str, Vec<Option<usize>>)> = notebook
+            .cells
+            .iter()
+            .map(|c| (c.cell_type.as_str(), c.source.as_str(), c.lines.clone()))
+            .collect();
+        assert_eq!(
+            cells,
+            vec![
+                (
+                    "markdown",
+                    "# Title\n\nSome *text*.",
+                    vec![Some(6), Some(7), Some(8)]
+                ),
+                (
+                    "code",
+                    "def f():\n    return \"hi\"\n",
+                    vec![Some(25), Some(26)]
+                ),
+                ("raw", "a\nb", vec![Some(32), Some(32)]),
+            ]
+        );
+
+        assert!(parse("{\"cells\": 1}").is_err());
+        assert!(parse("not json").is_err());
+    }
+
+    #[test]
+    fn highlights_cells() {
+        let result = highlight(Query {
+            filepath: "a.ipynb".to_string(),
+            code: NOTEBOOK.to_string(),
+            css: true,
+            notebook: true,
+            ..Query::default()
+        });
+        assert_eq!(result["plaintext"], false);
+        let cells = result["data"].as_array().unwrap();
+        assert_eq!(cells.len(), 3);
+        assert_eq!(cells[0]["language"], "Markdown");
+        assert_eq!(cells[1]["cell_type"], "code");
+        assert_eq!(cells[1]["language"], "Python");
+        assert_eq!(cells[1]["lines"], serde_json::json!([25, 26]));
+        let html = cells[1]["data"].as_str().unwrap();
+        assert!(html.contains("hl-python\">def</span>"), "{}", html);
+        assert_eq!(cells[2]["language"], "Plain Text");
+
+        let result = highlight(Query {
+            code: "{}".to_string(),
+            css: true,
+            notebook: true,
+            ..Query::default()
+        });
+        assert_eq!(result["code"], "invalid_notebook");
+    }
+
+    #[test]
+    fn limits_the_whole_notebook() {
+        if *limits::MAX_CODE_BYTES == 0 || !*limits::TOO_LARGE_AS_PLAINTEXT {
+            return;
+        }
+        // Whitespace after the JSON is valid, so the notebook would parse.
+        let padding = format!("{}\n", " ".repeat(99)).repeat(*limits::MAX_CODE_BYTES / 100 + 1);
+        let result = highlight(Query {
+            code: format!("{}{}", NOTEBOOK, padding),
+            css: true,
+            notebook: true,
+            ..Query::default()
+        });
+        assert_eq!(result["plaintext"], true);
+        let reason = result["reason"].as_str().unwrap();
+        assert!(reason.contains("more than the limit"), "{}", reason);
+    }
+}
@@ -0,0 +1,276 @@
+use crate::class_map::ClassMap;
+use crate::engine::ParsedLine;
+use crate::semantic_tokens::{self, Token};
+use serde_json::{json, Value};
+use syntect::parsing::Scope;
+
+// SYNTAX_KINDS are the values of SCIP's `SyntaxKind` enum which scopes are
+// mapped to, see https://github.com/sourcegraph/scip/blob/main/scip.proto
+const SYNTAX_KINDS: &[(&str, i32)] = &[
+    ("Comment", 1),
+    ("PunctuationDelimiter", 2),
+    ("PunctuationBracket", 3),
+    ("Keyword", 4),
+    ("IdentifierOperator", 5),
+    ("Identifier", 6),
+    ("IdentifierBuiltin", 7),
+    ("IdentifierNull", 8),
+    ("IdentifierConstant", 9),
+    ("IdentifierParameter", 11),
+    ("IdentifierNamespace", 14),
+    ("IdentifierFunction", 15),
+    ("IdentifierFunctionDefinition", 16),
+    ("IdentifierMacro", 17),
+    ("IdentifierMacroDefinition", 18),
+    ("IdentifierType", 19),
+    ("IdentifierBuiltinType", 20),
+    ("IdentifierAttribute", 21),
+    ("StringLiteral", 27),
+    ("StringLiteralEscape", 28),
+    ("StringLiteralKey", 30),
+    ("CharacterLiteral", 31),
+    ("NumericLiteral", 32),
+    ("BooleanLiteral", 33),
+    ("Tag", 34),
+    ("TagAttribute", 35),
+    ("TagDelimiter", 36),
+];
+
+// SCOPE_KINDS maps scope prefixes to syntax kinds, like SCOPE_TYPES of
+// semantic_tokens.rs does to LSP token types.
+const SCOPE_KINDS: &[(&str, &str)] = &[
+    ("comment", "Comment"),
+    ("constant", "IdentifierConstant"),
+    ("constant.character", "CharacterLiteral"),
+    ("constant.character.escape", "StringLiteralEscape"),
+    ("constant.language", "IdentifierBuiltin"),
+    ("constant.language.boolean", "BooleanLiteral"),
+    ("constant.language.null", "IdentifierNull"),
+    ("constant.numeric", "NumericLiteral"),
+    ("entity.name", "Identifier"),
+    ("entity.name.class", "IdentifierType"),
+    ("entity.name.constant", "IdentifierConstant"),
+    ("entity.name.enum", "IdentifierType"),
+    ("entity.name.function", "IdentifierFunctionDefinition"),
+    ("entity.name.interface", "IdentifierType"),
+    ("entity.name.macro", "IdentifierMacroDefinition"),
+    ("entity.name.namespace", "IdentifierNamespace"),
+    ("entity.name.struct", "IdentifierType"),
+    ("entity.name.tag", "Tag"),
+    ("entity.name.type", "IdentifierType"),
+    ("entity.other.attribute-name", "TagAttribute"),
+    ("entity.other.inherited-class", "IdentifierType"),
+    ("keyword", "Keyword"),
+    ("keyword.operator", "IdentifierOperator"),
+    ("keyword.operator.word", "Keyword"),
+    ("meta.annotation", "IdentifierAttribute"),
+    ("meta.decorator", "IdentifierAttribute"),
+    ("punctuation.accessor", "PunctuationDelimiter"),
+    ("punctuation.definition.comment", ""),
+    ("punctuation.definition.string", ""),
+    ("punctuation.definition.tag", "TagDelimiter"),
+    ("punctuation.section", "PunctuationBracket"),
+    ("punctuation.separator", "PunctuationDelimiter"),
+    ("punctuation.terminator", "PunctuationDelimiter"),
+    ("storage", "Keyword"),
+    ("storage.type", "IdentifierBuiltinType"),
+    ("storage.type.class", "Keyword"),
+    ("storage.type.enum", "Keyword"),
+    ("storage.type.function", "Keyword"),
+    ("storage.type.interface", "Keyword"),
+    ("storage.type.struct", "Keyword"),
+    ("string", "StringLiteral"),
+    ("support.class", "IdentifierBuiltinType"),
+    ("support.constant", "IdentifierBuiltin"),
+    ("support.function", "IdentifierBuiltin"),
+    ("support.type", "IdentifierBuiltinType"),
+    ("support.type.property-name", "StringLiteralKey"),
+    ("variable", "Identifier"),
+    ("variable.function", "IdentifierFunction"),
+    ("variable.language", "IdentifierBuiltin"),
+    ("variable.other.constant", "IdentifierConstant"),
+    ("variable.parameter", "IdentifierParameter"),
+];
+
+// SCIP's `PositionEncoding` for positions in UTF-16 code units, which is what
+// semantic_tokens::tokens counts.
+const UTF16_CODE_UNIT_OFFSET_FROM_LINE_START: u64 = 2;
+
+lazy_static! {
+    static ref KINDS: ClassMap = ClassMap::from_rules("syntax kinds", SCOPE_KINDS);
+}
+
+/// Format is how occurrences are serialized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// A JSON array of occurrences in the protobuf JSON mapping of SCIP's
+    /// `Occurrence`, e.g. `{"range": [0, 0, 7], "syntaxKind": "Keyword"}`.
+    Json,
+
+    /// A base64-encoded SCIP `Document` protobuf message.
+    Protobuf,
+}
+
+impl Format {
+    /// from_name returns the format of the given name in queries.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Format::Json),
+            "protobuf" => Some(Format::Protobuf),
+            _ => None,
+        }
+    }
+}
+
+/// Occurrence is a range of a single line with its syntax kind.
+pub type Occurrence = Token<&'static str>;
+
+/// occurrences returns the syntax highlighting occurrences of the code, i.e.
+/// the ranges of text which have a syntax kind, given the ops of each of its
+/// lines. Lines which are not highlighted have no occurrences.
+pub fn occurrences(s: &str, parsed_lines: impl Iterator<Item = ParsedLine>) -> Vec<Occurrence> {
+    semantic_tokens::tokens(s, parsed_lines, syntax_kind)
+}
+
+// syntax_kind returns the syntax kind of text with the given scopes, if any.
+fn syntax_kind(stack: &[Scope]) -> Option<&'static str> {
+    stack.iter().rev().find_map(|&scope| {
+        let name = KINDS.classes(scope)?;
+        SYNTAX_KINDS
+            .iter()
+            .find(|(kind, _)| *kind == name)
+            .map(|(kind, _)| *kind)
+    })
+}
+
+fn kind_value(kind: &str) -> i32 {
+    SYNTAX_KINDS
+        .iter()
+        .find(|(k, _)| *k == kind)
+        .map_or(0, |(_, value)| *value)
+}
+
+/// serialize returns the occurrences in the given format, as the `data` of
+/// the response.
+pub fn serialize(
+    format: Format,
+    relative_path: &str,
+    language: &str,
+    occurrences: &[Occurrence],
+) -> Value {
+    match format {
+        Format::Json => occurrences
+            .iter()
+            .map(|o| json!({"range": [o.line, o.start, o.start + o.len], "syntaxKind": o.kind}))
+            .collect(),
+        Format::Protobuf => {
+            base64::encode(to_protobuf(relative_path, language, occurrences)).into()
+        }
+    }
+}
+
+// to_protobuf encodes the occurrences as a SCIP `Document`. Its few fields are
+// encoded by hand, rather than pulling in a protobuf code generator.
+fn to_protobuf(relative_path: &str, language: &str, occurrences: &[Occurrence]) -> Vec<u8> {
+    let mut document = Vec::new();
+    write_bytes(&mut document, 1, relative_path.as_bytes());
+    for o in occurrences {
+        // Single-line ranges are [line, start, end], packed.
+        let mut range = Vec::new();
+        for &n in &[o.line, o.start, o.start + o.len] {
+            write_varint(&mut range, u64::from(n));
+        }
+        let mut occurrence = Vec::new();
+        write_bytes(&mut occurrence, 1, &range);
+        write_key(&mut occurrence, 5, 0);
+        write_varint(&mut occurrence, kind_value(o.kind) as u64);
+        write_bytes(&mut document, 2, &occurrence);
+    }
+    write_bytes(&mut document, 4, language.as_bytes());
+    write_key(&mut document, 6, 0);
+    write_varint(&mut document, UTF16_CODE_UNIT_OFFSET_FROM_LINE_START);
+    document
+}
+
+fn write_key(buf: &mut Vec<u8>, field: u32, wire_type: u32) {
+    write_varint(buf, u64::from(field << 3 | wire_type));
+}
+
+// write_bytes writes a length-delimited field.
+fn write_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_key(buf, field, 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{occurrences, serialize, to_protobuf, Format, Occurrence};
+    use crate::engine::{self, Engine};
+    use syntect::parsing::SyntaxSet;
+
+    #[test]
+    fn go_occurrences() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let sr = ss.find_syntax_by_extension("go").unwrap();
+        let code = "package main\n\nfunc main() {\n\tx := \"hi\" // done\n}\n";
+        let occurrences = occurrences(code, engine::parse(Engine::Syntect, code, &ss, sr, None));
+        let has = |line, start, len, kind| {
+            occurrences.contains(&Occurrence {
+                line,
+                start,
+                len,
+                kind,
+            })
+        };
+        assert!(has(0, 0, 7, "Keyword"), "{:?}", occurrences);
+        assert!(has(0, 8, 4, "Identifier"), "{:?}", occurrences);
+        assert!(
+            has(2, 5, 4, "IdentifierFunctionDefinition"),
+            "{:?}",
+            occurrences
+        );
+        assert!(has(3, 6, 4, "StringLiteral"), "{:?}", occurrences);
+        assert!(has(3, 11, 7, "Comment"), "{:?}", occurrences);
+
+        assert_eq!(
+            serialize(Format::Json, "main.go", "Go", &occurrences[..1]),
+            serde_json::json!([{"range": [0, 0, 7], "syntaxKind": "Keyword"}])
+        );
+    }
+
+    #[test]
+    fn protobuf_document() {
+        let occurrences = [
+            Occurrence {
+                line: 0,
+                start: 0,
+                len: 7,
+                kind: "Keyword",
+            },
+            Occurrence {
+                line: 200,
+                start: 8,
+                len: 4,
+                kind: "Identifier",
+            },
+        ];
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            0x0a, 4, b'a', b'.', b'g', b'o',
+            0x12, 7, 0x0a, 3, 0, 0, 7, 0x28, 4,
+            0x12, 8, 0x0a, 4, 0xc8, 0x01, 8, 12, 0x28, 6,
+            0x22, 2, b'G', b'o',
+            0x30, 2,
+        ];
+        assert_eq!(to_protobuf("a.go", "Go", &occurrences), expected);
+    }
+}
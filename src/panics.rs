@@ -0,0 +1,71 @@
+use crate::config::env_flag;
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic;
+
+lazy_static! {
+    // Whether error responses for panics include the panic message and
+    // location. Off by default, as they reveal details of the server.
+    pub static ref PANIC_DETAIL: bool = env_flag("PANIC_DETAIL");
+}
+
+thread_local! {
+    // The message and location of the last panic on this thread.
+    static LAST_PANIC: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// install installs a panic hook which records the message and location of
+/// every panic, so that `take` can retrieve it after `catch_unwind`. Panics
+/// are still printed by the default hook too.
+pub fn install() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let message = match info.location() {
+            Some(location) => format!("{} at {}", message(info.payload()), location),
+            None => message(info.payload()),
+        };
+        LAST_PANIC.with(|p| *p.borrow_mut() = Some(message));
+        default_hook(info);
+    }));
+}
+
+/// take returns the message and location of the panic caught with the given
+/// payload. Without the panic hook, only the message is known.
+pub fn take(payload: &(dyn Any + Send)) -> String {
+    LAST_PANIC
+        .with(|p| p.borrow_mut().take())
+        .unwrap_or_else(|| message(payload))
+}
+
+/// message returns the message a panic was raised with.
+fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{message, take};
+    use std::panic;
+
+    #[test]
+    fn panic_messages() {
+        let literal = panic::catch_unwind(|| panic!("literal")).unwrap_err();
+        let formatted = panic::catch_unwind(|| panic!("formatted {}", 42)).unwrap_err();
+        let other = panic::catch_unwind(|| {
+            panic::resume_unwind(Box::new(42));
+        })
+        .unwrap_err();
+
+        assert_eq!(message(&*literal), "literal");
+        assert_eq!(message(&*formatted), "formatted 42");
+        assert_eq!(message(&*other), "unknown panic payload");
+        // The hook isn't installed in tests.
+        assert_eq!(take(&*formatted), "formatted 42");
+    }
+}
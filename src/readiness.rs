@@ -0,0 +1,134 @@
+use crate::config::env_or;
+use crate::css_table::{self, ClassedTableGenerator};
+use crate::themed;
+use crate::{SYNTAX_SET, THEME_SET};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket_contrib::json::JsonValue;
+use std::panic;
+use std::time::{Duration, Instant};
+use syntect::{html::ClassStyle, parsing::Scope};
+
+lazy_static! {
+    // The time the readiness checks may take in total before the server is
+    // considered not ready.
+    static ref READY_BUDGET: Duration = Duration::from_millis(env_or("READY_BUDGET_MS", 1000));
+}
+
+const SNIPPET: &str = "package main\n\nimport \"fmt\"\n\nfunc main() {\n\tfmt.Println(\"hello, world\") // greet\n}\n";
+
+// The theme used to check themed mode, which is always embedded.
+const THEME: &str = "InspiredGitHub";
+
+/// ready highlights a small snippet in both CSS and themed mode, bypassing the
+/// result cache, so that a server whose highlighting is broken (e.g. by a
+/// poisoned SCOPE_REPO mutex) is reported as not ready, unlike by `/health`.
+pub fn ready() -> status::Custom<JsonValue> {
+    let start = Instant::now();
+    let css = check(|| {
+        // The class strings of the snippet's scopes are cached per thread,
+        // so also check that SCOPE_REPO itself is usable.
+        let scope = Scope::new("source.go").map_err(|err| format!("{:?}", err))?;
+        css_table::scope_to_classes(&scope, "hl-");
+        SYNTAX_SET.with(|ss| {
+            let sr = ss
+                .find_syntax_by_extension("go")
+                .ok_or("Go syntax not found")?;
+            let html = ClassedTableGenerator::new(
+                ss,
+                sr,
+                SNIPPET,
+                None,
+                ClassStyle::SpacedPrefixed { prefix: "hl-" },
+            )
+            .generate();
+            expect_highlighted(&html, "hl-keyword")
+        })
+    });
+    let themed = check(|| {
+        let theme = THEME_SET.themes.get(THEME).ok_or("theme not found")?;
+        SYNTAX_SET.with(|ss| {
+            let sr = ss
+                .find_syntax_by_extension("go")
+                .ok_or("Go syntax not found")?;
+            let html = themed::highlighted_html_for_string(SNIPPET, ss, sr, theme, None);
+            expect_highlighted(&html, "style=\"color:")
+        })
+    });
+    let elapsed = start.elapsed();
+
+    let mut ready = css["ok"] == true && themed["ok"] == true;
+    let mut error = None;
+    if ready && elapsed > *READY_BUDGET {
+        ready = false;
+        error = Some(format!(
+            "checks took {}ms, over the budget of {}ms",
+            elapsed.as_millis(),
+            READY_BUDGET.as_millis()
+        ));
+    }
+
+    let body = json!({
+        "ready": ready,
+        "error": error,
+        "duration_ms": elapsed.as_secs_f64() * 1000.0,
+        "checks": {
+            "css": css,
+            "themed": themed,
+        },
+        "syntaxes": SYNTAX_SET.with(|ss| ss.syntaxes().len()),
+        "themes": THEME_SET.themes.len(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "build": option_env!("VERSION").unwrap_or("dev"),
+        "syntect": option_env!("SYNTECT_REVISION"),
+    });
+    let status = if ready {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+    status::Custom(status, body)
+}
+
+// check runs a single readiness check, which must neither fail nor panic.
+fn check<F: FnOnce() -> Result<(), String> + panic::UnwindSafe>(f: F) -> serde_json::Value {
+    let start = Instant::now();
+    let result = match panic::catch_unwind(f) {
+        Ok(result) => result,
+        Err(_) => Err("panic".to_string()),
+    };
+    let duration_ms = start.elapsed().as_secs_f64() * 1000.0;
+    match result {
+        Ok(()) => serde_json::json!({"ok": true, "duration_ms": duration_ms}),
+        Err(err) => serde_json::json!({"ok": false, "duration_ms": duration_ms, "error": err}),
+    }
+}
+
+fn expect_highlighted(html: &str, needle: &str) -> Result<(), String> {
+    if html.contains(needle) && html.contains("hello, world") {
+        Ok(())
+    } else {
+        Err(format!("unexpected output: {}", html))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+
+    #[test]
+    fn ready() {
+        // Loading the syntaxes and themes doesn't count towards the budget
+        // of a warm server.
+        crate::SYNTAX_SET.with(|_| ());
+        assert!(!crate::THEME_SET.themes.is_empty());
+
+        let response = super::ready();
+        assert_eq!(response.0, Status::Ok, "{:?}", *response.1);
+        assert_eq!(response.1["ready"], true);
+        // Set by build.rs from the Cargo.lock.
+        let rev = response.1["syntect"].as_str().unwrap();
+        assert_eq!(rev.len(), 40, "{}", rev);
+        assert!(rev.chars().all(|c| c.is_ascii_hexdigit()), "{}", rev);
+    }
+}
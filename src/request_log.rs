@@ -0,0 +1,117 @@
+use crate::config::env_or;
+use crate::Query;
+use rocket_contrib::json::JsonValue;
+use std::cell::RefCell;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    // Whether to log every request. Slow requests and those which panicked
+    // are logged regardless.
+    static ref LOG_REQUESTS: bool = env_or("LOG_REQUESTS", false);
+
+    // Requests taking longer than this are logged with their full filepath,
+    // so that the input can be reproduced.
+    static ref SLOW_REQUEST: Duration = Duration::from_millis(env_or("LOG_SLOW_REQUEST_MS", 1000));
+}
+
+thread_local! {
+    // The syntax detected for the request being handled on this thread.
+    static SYNTAX: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// set_syntax records the syntax detected for the current request.
+pub fn set_syntax(name: &str) {
+    SYNTAX.with(|s| *s.borrow_mut() = Some(name.to_string()));
+}
+
+/// Entry collects what we log about a single request. It is created before
+/// the query is handed to `highlight()`, and finished with its result.
+pub struct Entry {
+    filepath: String,
+    extension: String,
+    bytes: usize,
+    lines: usize,
+    css: bool,
+    theme: String,
+    start: Instant,
+}
+
+impl Entry {
+    pub fn new(q: &Query) -> Self {
+        SYNTAX.with(|s| *s.borrow_mut() = None);
+        let extension = if q.filepath.is_empty() {
+            q.extension.clone()
+        } else {
+            Path::new(&q.filepath)
+                .extension()
+                .and_then(|x| x.to_str())
+                .unwrap_or("")
+                .to_string()
+        };
+        Entry {
+            filepath: q.filepath.clone(),
+            extension,
+            bytes: q.code.len(),
+            lines: q.code.lines().count(),
+            css: q.css,
+            theme: if q.css {
+                String::new()
+            } else {
+                q.theme.clone()
+            },
+            start: Instant::now(),
+        }
+    }
+
+    /// finish logs the request as a single line of JSON, given its result or
+    /// the message of the panic it caused.
+    pub fn finish(self, result: Result<&JsonValue, &str>) {
+        let duration = self.start.elapsed();
+        let slow = duration > *SLOW_REQUEST;
+        if !*LOG_REQUESTS && !slow && result.is_ok() {
+            return;
+        }
+
+        let (outcome, panic) = match result {
+            Ok(result) if result.get("error").is_some() => (
+                result
+                    .get("code")
+                    .and_then(|c| c.as_str())
+                    .unwrap_or("error")
+                    .to_string(),
+                None,
+            ),
+            Ok(result) if result.get("reason").is_some() => {
+                ("plaintext_fallback".to_string(), None)
+            }
+            Ok(_) => ("ok".to_string(), None),
+            Err(panic) => ("panic".to_string(), Some(panic)),
+        };
+        let mut line = serde_json::json!({
+            "extension": self.extension,
+            "syntax": SYNTAX.with(|s| s.borrow_mut().take()),
+            "bytes": self.bytes,
+            "lines": self.lines,
+            "css": self.css,
+            "theme": self.theme,
+            "duration_ms": duration.as_secs_f64() * 1000.0,
+            "outcome": outcome,
+        });
+        if let Some(reason) = result.ok().and_then(|r| r.get("reason")) {
+            line["reason"] = reason.clone();
+        }
+        if let Some(panic) = panic {
+            line["panic"] = panic.into();
+        }
+        if slow {
+            line["slow"] = true.into();
+        }
+        // The filepath lets us reproduce slow requests and report panics
+        // upstream.
+        if slow || panic.is_some() {
+            line["filepath"] = self.filepath.into();
+        }
+        println!("{}", line);
+    }
+}
@@ -0,0 +1,322 @@
+use crate::class_map::ClassMap;
+use crate::engine::ParsedLine;
+use serde_json::{json, Value};
+use syntect::{
+    parsing::{Scope, ScopeStack},
+    util::LinesWithEndings,
+};
+
+/// TOKEN_TYPES are the standard LSP semantic token types, in the order of
+/// their indices in the encoded tokens.
+pub const TOKEN_TYPES: &[&str] = &[
+    "namespace",
+    "type",
+    "class",
+    "enum",
+    "interface",
+    "struct",
+    "typeParameter",
+    "parameter",
+    "variable",
+    "property",
+    "enumMember",
+    "event",
+    "function",
+    "method",
+    "macro",
+    "keyword",
+    "modifier",
+    "comment",
+    "string",
+    "number",
+    "regexp",
+    "operator",
+    "decorator",
+];
+
+/// TOKEN_MODIFIERS are the standard LSP semantic token modifiers, in the
+/// order of their bits in the encoded tokens.
+pub const TOKEN_MODIFIERS: &[&str] = &[
+    "declaration",
+    "definition",
+    "readonly",
+    "static",
+    "deprecated",
+    "abstract",
+    "async",
+    "modification",
+    "documentation",
+    "defaultLibrary",
+];
+
+// SCOPE_TYPES maps scope prefixes to token types, like the class maps of
+// class_map.rs. A token gets the type of the innermost scope which has one;
+// scopes mapped to "" defer to the scopes they're nested in, so that e.g.
+// the quotes of a string are part of the string token.
+const SCOPE_TYPES: &[(&str, &str)] = &[
+    ("comment", "comment"),
+    ("constant", "variable"),
+    ("constant.character.escape", "string"),
+    ("constant.language", "keyword"),
+    ("constant.numeric", "number"),
+    ("entity.name", "variable"),
+    ("entity.name.class", "class"),
+    ("entity.name.enum", "enum"),
+    ("entity.name.function", "function"),
+    ("entity.name.interface", "interface"),
+    ("entity.name.macro", "macro"),
+    ("entity.name.namespace", "namespace"),
+    ("entity.name.struct", "struct"),
+    ("entity.name.type", "type"),
+    ("entity.other.attribute-name", "property"),
+    ("entity.other.inherited-class", "class"),
+    ("keyword", "keyword"),
+    ("keyword.operator", "operator"),
+    ("keyword.operator.word", "keyword"),
+    ("meta.annotation", "decorator"),
+    ("meta.decorator", "decorator"),
+    ("punctuation.definition.comment", ""),
+    ("punctuation.definition.string", ""),
+    ("storage", "keyword"),
+    ("storage.type", "type"),
+    ("storage.type.class", "keyword"),
+    ("storage.type.function", "keyword"),
+    ("string", "string"),
+    ("string.regexp", "regexp"),
+    ("support.class", "class"),
+    ("support.constant", "variable"),
+    ("support.function", "function"),
+    ("support.type", "type"),
+    ("support.type.property-name", "property"),
+    ("variable", "variable"),
+    ("variable.function", "function"),
+    ("variable.other.member", "property"),
+    ("variable.other.property", "property"),
+    ("variable.parameter", "parameter"),
+];
+
+// SCOPE_MODIFIERS maps scope prefixes to the modifiers of the tokens whose
+// type is determined by a matching scope.
+const SCOPE_MODIFIERS: &[(&str, &str)] = &[
+    ("comment.block.documentation", "documentation"),
+    ("constant.other", "readonly"),
+    ("entity.name", "declaration"),
+    ("entity.name.constant", "readonly"),
+    ("invalid.deprecated", "deprecated"),
+    ("support", "defaultLibrary"),
+    ("support.constant", "readonly"),
+    ("variable.language", "defaultLibrary"),
+    ("variable.other.constant", "readonly"),
+];
+
+lazy_static! {
+    static ref TYPES: ClassMap = ClassMap::from_rules("semantic token types", SCOPE_TYPES);
+    static ref MODIFIERS: Vec<(Scope, u32)> = SCOPE_MODIFIERS
+        .iter()
+        .map(|(prefix, modifier)| {
+            let bit = TOKEN_MODIFIERS.iter().position(|m| m == modifier).unwrap();
+            (Scope::new(prefix).unwrap(), 1 << bit)
+        })
+        .collect();
+}
+
+/// legend returns the legend of the encoded tokens, in the shape of LSP's
+/// `SemanticTokensLegend`.
+pub fn legend() -> Value {
+    json!({
+        "tokenTypes": TOKEN_TYPES,
+        "tokenModifiers": TOKEN_MODIFIERS,
+    })
+}
+
+/// encode returns the semantic tokens of the code in LSP's relative encoding:
+/// five integers per token, the line delta, the start character delta (from
+/// the previous token's start on the same line), the length, the token type
+/// and the token modifiers bitset. Characters are counted in UTF-16 code
+/// units, LSP's default position encoding.
+///
+/// Tokens never span lines. Lines which are not highlighted have no tokens.
+pub fn encode(s: &str, parsed_lines: impl Iterator<Item = ParsedLine>) -> Vec<u32> {
+    let tokens = tokens(s, parsed_lines, token_type);
+    let mut data = Vec::with_capacity(tokens.len() * 5);
+    let (mut last_line, mut last_start) = (0, 0);
+    for token in tokens {
+        let delta_line = token.line - last_line;
+        let delta_start = if delta_line == 0 {
+            token.start - last_start
+        } else {
+            token.start
+        };
+        let (token_type, modifiers) = token.kind;
+        data.extend_from_slice(&[delta_line, delta_start, token.len, token_type, modifiers]);
+        last_line = token.line;
+        last_start = token.start;
+    }
+    data
+}
+
+/// Token is a run of text on a single line, whose position and length are
+/// counted in UTF-16 code units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token<T> {
+    pub line: u32,
+    pub start: u32,
+    pub len: u32,
+    pub kind: T,
+}
+
+/// tokens returns the tokens of the code, given the ops of each of its lines,
+/// where kind determines the kind of text with the given scope stack, if it is
+/// a token at all. Adjacent text of the same kind is merged into a single
+/// token, and line endings are never part of a token. Lines without ops have
+/// no tokens.
+pub fn tokens<T: Copy + PartialEq>(
+    s: &str,
+    parsed_lines: impl Iterator<Item = ParsedLine>,
+    kind: impl Fn(&[Scope]) -> Option<T>,
+) -> Vec<Token<T>> {
+    let mut stack = ScopeStack::new();
+    let mut tokens: Vec<Token<T>> = Vec::new();
+    let mut push = |line: usize, start: u32, text: &str, kind: Option<T>| {
+        let len = text.encode_utf16().count() as u32;
+        let kind = match kind {
+            Some(kind) => kind,
+            None => return len,
+        };
+        let line = line as u32;
+        match tokens.last_mut() {
+            Some(last)
+                if last.line == line && last.start + last.len == start && last.kind == kind =>
+            {
+                last.len += len
+            }
+            _ => tokens.push(Token {
+                line,
+                start,
+                len,
+                kind,
+            }),
+        }
+        len
+    };
+    for (i, (line, ops)) in LinesWithEndings::from(s).zip(parsed_lines).enumerate() {
+        let ops = match ops {
+            Some(ops) => ops,
+            None => continue,
+        };
+        let end = line.trim_end_matches(|c| c == '\n' || c == '\r').len();
+        let mut start = 0;
+        let mut col = 0;
+        for (offset, op) in ops {
+            let offset = offset.min(end);
+            if offset > start {
+                col += push(i, col, &line[start..offset], kind(stack.as_slice()));
+                start = offset;
+            }
+            stack.apply(&op);
+        }
+        if end > start {
+            push(i, col, &line[start..end], kind(stack.as_slice()));
+        }
+    }
+    tokens
+}
+
+// token_type returns the token type index and modifiers of text with the
+// given scopes, if it is a token at all.
+fn token_type(stack: &[Scope]) -> Option<(u32, u32)> {
+    stack.iter().rev().find_map(|&scope| {
+        let name = TYPES.classes(scope)?;
+        let index = TOKEN_TYPES.iter().position(|t| *t == name)?;
+        let modifiers = MODIFIERS
+            .iter()
+            .filter(|(prefix, _)| prefix.is_prefix_of(scope))
+            .fold(0, |bits, (_, bit)| bits | bit);
+        Some((index as u32, modifiers))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, TOKEN_MODIFIERS, TOKEN_TYPES};
+    use crate::engine::{self, Engine};
+    use syntect::parsing::SyntaxSet;
+
+    // decode returns the tokens as (line, start, length, type, modifiers).
+    fn decode(data: &[u32]) -> Vec<(u32, u32, u32, &'static str, Vec<&'static str>)> {
+        let (mut line, mut start) = (0, 0);
+        data.chunks(5)
+            .map(|t| {
+                if t[0] > 0 {
+                    start = 0;
+                }
+                line += t[0];
+                start += t[1];
+                let modifiers = (0..TOKEN_MODIFIERS.len())
+                    .filter(|bit| t[4] & (1 << bit) != 0)
+                    .map(|bit| TOKEN_MODIFIERS[bit])
+                    .collect();
+                (line, start, t[2], TOKEN_TYPES[t[3] as usize], modifiers)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn go_tokens() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let sr = ss.find_syntax_by_extension("go").unwrap();
+        let code = "package main\n\nfunc main() {\n\tprintln(\"hi\") // done\n}\n";
+        let data = encode(code, engine::parse(Engine::Syntect, code, &ss, sr, None));
+        assert_eq!(data.len() % 5, 0);
+        assert_eq!(&data[..5], &[0, 0, 7, 15, 0]);
+
+        let tokens = decode(&data);
+        assert!(
+            tokens.contains(&(0, 0, 7, "keyword", vec![])),
+            "{:?}",
+            tokens
+        );
+        assert!(
+            tokens.contains(&(2, 5, 4, "function", vec!["declaration"])),
+            "{:?}",
+            tokens
+        );
+        // The quotes are part of the string.
+        assert!(
+            tokens.contains(&(3, 9, 4, "string", vec![])),
+            "{:?}",
+            tokens
+        );
+        // The comment doesn't include the newline.
+        assert!(
+            tokens.contains(&(3, 15, 7, "comment", vec![])),
+            "{:?}",
+            tokens
+        );
+    }
+
+    #[test]
+    fn utf16_columns_and_long_lines() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let sr = ss.find_syntax_by_extension("js").unwrap();
+        let code = "var s = \"é😀\"; var n = 1;\nvar long = \"xxxxxxxxxxxxxxxxxxxxxxxxxxxxxx\";\nvar m = 2;\n";
+        let parsed_lines = engine::parse(Engine::Syntect, code, &ss, sr, Some(30));
+        let tokens = decode(&encode(code, parsed_lines));
+        assert!(
+            tokens.contains(&(0, 8, 5, "string", vec![])),
+            "{:?}",
+            tokens
+        );
+        assert!(
+            tokens.contains(&(0, 23, 1, "number", vec![])),
+            "{:?}",
+            tokens
+        );
+        assert!(tokens.iter().all(|t| t.0 != 1), "{:?}", tokens);
+        assert!(
+            tokens.contains(&(2, 8, 1, "number", vec![])),
+            "{:?}",
+            tokens
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! Golden-file snapshot tests.
+//!
+//! Every file in `testdata/samples` is highlighted in both CSS and themed
+//! mode, and the output is compared against the snapshots stored in
+//! `testdata/snapshots`. This way, grammar updates show up as reviewable
+//! diffs of the snapshots instead of surprising us in production.
+//!
+//! Missing snapshots fail the tests just like changed output does. To record
+//! new snapshots or accept changed output, run the tests with
+//! `UPDATE_SNAPSHOTS=1` and review the diff.
+
+use crate::config::env_flag;
+use crate::{highlight, Query, SYNTAX_SET};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SAMPLES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/samples");
+const SNAPSHOTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/snapshots");
+
+// The theme used for themed mode snapshots.
+const THEME: &str = "InspiredGitHub";
+
+fn samples() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(SAMPLES)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    paths
+}
+
+// render returns the snapshot of highlighting the sample: the syntax that was
+// detected, whether it was plaintext, and the HTML. CSS tables are split into
+// one row per line so that diffs stay readable.
+fn render(path: &Path, css: bool) -> String {
+    let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
+    let code = fs::read_to_string(path).unwrap();
+    let result = highlight(Query {
+        filepath: file_name,
+        code,
+        css,
+        theme: THEME.to_string(),
+        ..Query::default()
+    });
+    let data = match result.get("data").and_then(|v| v.as_str()) {
+        Some(data) => data,
+        None => panic!("{}: {:?}", path.display(), *result),
+    };
+    let data = if css {
+        data.replace("<tr>", "\n<tr>")
+    } else {
+        data.to_string()
+    };
+    format!("plaintext: {}\n{}\n", result["plaintext"], data.trim_end())
+}
+
+#[test]
+fn snapshots() {
+    let update = env_flag("UPDATE_SNAPSHOTS");
+    let mut mismatched = Vec::new();
+    let mut missing = Vec::new();
+    for path in samples() {
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        for &(mode, css) in &[("css", true), ("themed", false)] {
+            let actual = render(&path, css);
+            let snapshot = Path::new(SNAPSHOTS).join(format!("{}.{}.html", file_name, mode));
+            match fs::read_to_string(&snapshot) {
+                Ok(expected) if expected == actual => {}
+                _ if update => fs::write(&snapshot, actual).unwrap(),
+                Ok(_) => mismatched.push(snapshot.display().to_string()),
+                Err(_) => missing.push(snapshot.display().to_string()),
+            }
+        }
+    }
+    assert!(
+        missing.is_empty(),
+        "snapshots are missing, run with UPDATE_SNAPSHOTS=1 to record them and commit \
+         them:\n{}",
+        missing.join("\n")
+    );
+    assert!(
+        mismatched.is_empty(),
+        "highlighting output differs from the snapshots, re-run with UPDATE_SNAPSHOTS=1 \
+         and review the diff if this is expected:\n{}",
+        mismatched.join("\n")
+    );
+}
+
+// Every snapshot must belong to a sample, so that renamed or deleted samples
+// don't leave stale snapshots behind.
+#[test]
+fn no_stale_snapshots() {
+    let samples: Vec<String> = samples()
+        .iter()
+        .map(|p| p.file_name().unwrap().to_str().unwrap().to_string())
+        .collect();
+    for entry in fs::read_dir(SNAPSHOTS).unwrap() {
+        let name = entry.unwrap().file_name().to_str().unwrap().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let sample = name
+            .trim_end_matches(".css.html")
+            .trim_end_matches(".themed.html");
+        assert!(
+            samples.iter().any(|s| s == sample),
+            "snapshot {} has no sample in testdata/samples",
+            name
+        );
+    }
+}
+
+// Lists the syntaxes which have no sample yet (run with --nocapture).
+#[test]
+fn sample_coverage() {
+    let samples = samples();
+    SYNTAX_SET.with(|ss| {
+        let covered: Vec<&str> = samples
+            .iter()
+            .filter_map(|path| {
+                let file_name = path.file_name()?.to_str()?;
+                let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("");
+                ss.find_syntax_by_extension(file_name)
+                    .or_else(|| ss.find_syntax_by_extension(extension))
+                    .map(|sr| sr.name.as_str())
+            })
+            .collect();
+        for sr in ss.syntaxes() {
+            if !sr.hidden && !sr.file_extensions.is_empty() && !covered.contains(&sr.name.as_str())
+            {
+                println!("no snapshot sample for syntax {:?}", sr.name);
+            }
+        }
+    });
+}
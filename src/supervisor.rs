@@ -0,0 +1,935 @@
+use crate::breaker::{self, Breakers, Failure};
+use crate::config::env_or;
+use crate::{limits, Query};
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use syntect::parsing::SyntaxSet;
+
+// The largest request head (request line and headers) we accept.
+const MAX_HEAD_BYTES: usize = 64 * 1024;
+
+lazy_static! {
+    // The largest request body we accept: by default twice MAX_CODE_BYTES,
+    // for the escaping of the code in JSON, plus room for the other fields.
+    static ref MAX_BODY_BYTES: usize = env_or(
+        "SUPERVISOR_MAX_BODY_BYTES",
+        if *limits::MAX_CODE_BYTES > 0 {
+            2 * *limits::MAX_CODE_BYTES + MAX_HEAD_BYTES
+        } else {
+            64 * 1024 * 1024
+        },
+    );
+
+    // The syntaxes to tell the language of requests by, for the breakers.
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+}
+
+struct Worker {
+    port: u16,
+    child: Option<Child>,
+
+    // generation is incremented whenever the worker process is replaced, so
+    // that a request which timed out only restarts the process it was sent to.
+    generation: u64,
+    // killed is the last generation which the supervisor killed itself, as a
+    // request to it timed out. The other requests in flight on it fail then,
+    // through no fault of their own.
+    killed: u64,
+
+    in_flight: usize,
+    restarts: u64,
+}
+
+impl Worker {
+    fn spawn(&mut self, exe: &Path) {
+        self.generation += 1;
+        let child = Command::new(exe)
+            .env("ROCKET_ADDRESS", "127.0.0.1")
+            .env("ROCKET_PORT", self.port.to_string())
+            .spawn();
+        match child {
+            Ok(child) => self.child = Some(child),
+            Err(err) => eprintln!("worker on port {}: failed to start: {}", self.port, err),
+        }
+    }
+
+    // restart kills the worker process, if it is still running, and starts a
+    // new one in its place.
+    fn restart(&mut self, exe: &Path, reason: &str) {
+        eprintln!("worker on port {}: {}, restarting", self.port, reason);
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        self.restarts += 1;
+        self.spawn(exe);
+    }
+}
+
+struct Supervisor {
+    exe: PathBuf,
+    workers: Mutex<Vec<Worker>>,
+    timeout: Duration,
+    next: AtomicUsize,
+
+    // The time a client has to send its whole request.
+    read_timeout: Duration,
+    max_connections: usize,
+    connections: AtomicUsize,
+    metrics: Mutex<Metrics>,
+
+    // The circuit breakers shared by all workers. Unlike those of a worker,
+    // they survive the worker being killed, and count the failures of all
+    // workers towards the same threshold.
+    breakers: Mutex<Breakers>,
+}
+
+// Metrics are exported in the Prometheus text format on `GET /metrics`.
+#[derive(Default)]
+struct Metrics {
+    // The number of responses by status code.
+    responses: BTreeMap<u16, u64>,
+    // The total time spent handling requests.
+    seconds: f64,
+    // The number of connections turned away because too many were open.
+    rejected: u64,
+}
+
+// RequestError is why a request could not be read from a client.
+#[derive(Debug)]
+enum RequestError {
+    // The body is larger than MAX_BODY_BYTES, which holds its length.
+    TooLarge(usize),
+    // The client didn't send the whole request in time.
+    TimedOut,
+    Invalid(io::Error),
+}
+
+impl From<io::Error> for RequestError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => RequestError::TimedOut,
+            _ => RequestError::Invalid(err),
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+
+    // head is the request line and headers to send to the worker.
+    head: Vec<u8>,
+
+    body: Vec<u8>,
+}
+
+/// run implements the `supervise` subcommand.
+///
+/// It starts `WORKERS` copies of the server as child processes, listening on
+/// consecutive ports from `WORKER_BASE_PORT` on localhost, and proxies the
+/// requests it receives on `SUPERVISOR_LISTEN` to the least busy of them.
+/// Syntect can get stuck on bad grammar/file combinations, and a thread stuck
+/// in a regex cannot be stopped, so a worker which doesn't answer a request
+/// within `WORKER_TIMEOUT_SECS` is killed and replaced, as is one which exits.
+/// `GET /workers` reports the state and restart count of every worker, and
+/// `GET /metrics` exports metrics about requests and workers for Prometheus.
+///
+/// Clients must send their request within `SUPERVISOR_READ_TIMEOUT_SECS`, with
+/// a body of at most `SUPERVISOR_MAX_BODY_BYTES`, and at most
+/// `SUPERVISOR_MAX_CONNECTIONS` connections are handled at once, so that no
+/// client can exhaust the memory or threads of the supervisor.
+pub fn run(args: &[String]) -> i32 {
+    if !args.is_empty() {
+        eprintln!("usage: syntect_server supervise");
+        return 2;
+    }
+    let listen: String = env_or("SUPERVISOR_LISTEN", "0.0.0.0:9238".to_string());
+    let workers: u16 = env_or("WORKERS", 4);
+    let base_port: u16 = env_or("WORKER_BASE_PORT", 9240);
+    let timeout = Duration::from_secs(env_or("WORKER_TIMEOUT_SECS", 10));
+    let read_timeout = Duration::from_secs(env_or("SUPERVISOR_READ_TIMEOUT_SECS", 10));
+    let max_connections: usize = env_or("SUPERVISOR_MAX_CONNECTIONS", 256);
+
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => {
+            eprintln!("locating syntect_server executable: {}", err);
+            return 1;
+        }
+    };
+    let listener = match TcpListener::bind(&listen) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("listening on {}: {}", listen, err);
+            return 1;
+        }
+    };
+
+    let ports = (0..workers.max(1)).map(|i| base_port + i).collect();
+    let supervisor = Arc::new(Supervisor::new(
+        exe,
+        ports,
+        timeout,
+        read_timeout,
+        max_connections,
+        Breakers::from_env(),
+    ));
+    for worker in supervisor.workers.lock().unwrap().iter_mut() {
+        worker.spawn(&supervisor.exe);
+    }
+
+    let monitor = supervisor.clone();
+    thread::spawn(move || monitor.monitor());
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if !supervisor.accept() {
+                    supervisor.reject(stream);
+                    continue;
+                }
+                let supervisor = supervisor.clone();
+                thread::spawn(move || {
+                    supervisor.handle(stream);
+                    supervisor.connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Err(err) => eprintln!("accepting connection: {}", err),
+        }
+    }
+    0
+}
+
+impl Supervisor {
+    fn new(
+        exe: PathBuf,
+        ports: Vec<u16>,
+        timeout: Duration,
+        read_timeout: Duration,
+        max_connections: usize,
+        breakers: Breakers,
+    ) -> Self {
+        Supervisor {
+            exe,
+            workers: Mutex::new(
+                ports
+                    .into_iter()
+                    .map(|port| Worker {
+                        port,
+                        child: None,
+                        generation: 0,
+                        killed: 0,
+                        in_flight: 0,
+                        restarts: 0,
+                    })
+                    .collect(),
+            ),
+            timeout,
+            next: AtomicUsize::new(0),
+            read_timeout,
+            max_connections,
+            connections: AtomicUsize::new(0),
+            metrics: Mutex::new(Metrics::default()),
+            breakers: Mutex::new(breakers),
+        }
+    }
+
+    // accept counts a new connection, unless as many as max_connections are
+    // open already.
+    fn accept(&self) -> bool {
+        if self.connections.fetch_add(1, Ordering::SeqCst) < self.max_connections {
+            return true;
+        }
+        self.connections.fetch_sub(1, Ordering::SeqCst);
+        false
+    }
+
+    // reject turns the client away without reading its request. It is called
+    // from the accept loop, so it must not wait for the client.
+    fn reject(&self, mut client: TcpStream) {
+        self.metrics.lock().unwrap().rejected += 1;
+        let response = error_response(
+            503,
+            "Service Unavailable",
+            "too many connections",
+            "overloaded",
+        );
+        let _ = client.set_nonblocking(true);
+        let _ = client.write_all(&response);
+        let _ = client.shutdown(Shutdown::Both);
+    }
+
+    // monitor restarts workers whose process exited, e.g. because it crashed.
+    fn monitor(&self) {
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            let mut workers = self.workers.lock().unwrap();
+            for worker in workers.iter_mut() {
+                let exited = match &mut worker.child {
+                    Some(child) => match child.try_wait() {
+                        Ok(Some(status)) => Some(format!("exited with {}", status)),
+                        Ok(None) => None,
+                        Err(err) => Some(err.to_string()),
+                    },
+                    None => Some("not running".to_string()),
+                };
+                if let Some(reason) = exited {
+                    worker.restart(&self.exe, &reason);
+                }
+            }
+        }
+    }
+
+    fn handle(&self, mut client: TcpStream) {
+        let start = Instant::now();
+        let deadline = start + self.read_timeout;
+        let response = match read_request(&mut client, *MAX_BODY_BYTES, deadline) {
+            Ok(ref request) if request.method == "GET" && request.path == "/workers" => {
+                http_response(200, "OK", "application/json", &self.stats())
+            }
+            Ok(ref request) if request.method == "GET" && request.path == "/metrics" => {
+                http_response(200, "OK", "text/plain; version=0.0.4", &self.metrics())
+            }
+            Ok(request) => self.forward(&request),
+            Err(RequestError::TooLarge(len)) => error_response(
+                413,
+                "Payload Too Large",
+                &format!(
+                    "request body is {} bytes, more than the limit of {}",
+                    len, *MAX_BODY_BYTES
+                ),
+                "too_large",
+            ),
+            Err(RequestError::TimedOut) => error_response(
+                408,
+                "Request Timeout",
+                "timed out while reading the request",
+                "request_timeout",
+            ),
+            Err(RequestError::Invalid(err)) => {
+                error_response(400, "Bad Request", &err.to_string(), "bad_request")
+            }
+        };
+        let _ = client.set_write_timeout(Some(self.timeout));
+        let _ = client.write_all(&response);
+        let _ = client.shutdown(Shutdown::Both);
+
+        let mut metrics = self.metrics.lock().unwrap();
+        *metrics.responses.entry(status(&response)).or_insert(0) += 1;
+        metrics.seconds += start.elapsed().as_secs_f64();
+    }
+
+    // forward sends the request to the least busy worker and returns its
+    // response, restarting the worker if it doesn't respond in time. Requests
+    // to highlight code in a language whose breaker is tripped are answered
+    // with plain text right away instead.
+    fn forward(&self, request: &Request) -> Vec<u8> {
+        let query = highlight_query(request);
+        let syntax = query.as_ref().and_then(|q| {
+            let (syntax, is_plaintext, _) = crate::detect_syntax(&SYNTAX_SET, q)?;
+            Some(syntax.name.clone()).filter(|_| !is_plaintext)
+        });
+        if let (Some(q), Some(syntax)) = (&query, &syntax) {
+            let reason = self.breakers.lock().unwrap().reason(syntax, Instant::now());
+            if let Some(reason) = reason {
+                let result = crate::highlight_as_plaintext(&SYNTAX_SET, q, reason);
+                return http_response(200, "OK", "application/json", &result.0.to_string());
+            }
+        }
+
+        let start = Instant::now();
+        let (result, killed) = self.exchange(request);
+        if let Some(syntax) = &syntax {
+            let failure = match &result {
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => Some(Failure::Timeout),
+                // The worker is still starting, which is no fault of the code.
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => None,
+                // The supervisor killed the worker for another request.
+                Err(_) if killed => None,
+                Err(_) => Some(Failure::Crash),
+                Ok(response) if find(response, b"\"code\":\"panic\"").is_some() => {
+                    Some(Failure::Panic)
+                }
+                Ok(_) if breaker::is_slow(start.elapsed()) => Some(Failure::Timeout),
+                Ok(_) => None,
+            };
+            if let Some(failure) = failure {
+                let mut breakers = self.breakers.lock().unwrap();
+                breakers.record(syntax, failure, Instant::now());
+            }
+        }
+
+        match result {
+            Ok(response) => response,
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => error_response(
+                504,
+                "Gateway Timeout",
+                "timed out while highlighting code",
+                "timeout",
+            ),
+            Err(_) if killed => error_response(
+                503,
+                "Service Unavailable",
+                "worker was restarted as another request timed out, try again",
+                "worker_restarted",
+            ),
+            Err(err) => error_response(
+                502,
+                "Bad Gateway",
+                &format!("worker failed: {}", err),
+                "worker_failed",
+            ),
+        }
+    }
+
+    // exchange sends the request to the least busy worker and returns its
+    // response, restarting the worker if it doesn't respond in time. It also
+    // returns whether the supervisor killed the worker while the request was
+    // in flight, because another request to it timed out.
+    fn exchange(&self, request: &Request) -> (io::Result<Vec<u8>>, bool) {
+        let (i, port, generation) = {
+            let mut workers = self.workers.lock().unwrap();
+            let in_flight: Vec<usize> = workers.iter().map(|w| w.in_flight).collect();
+            let i = least_busy(&in_flight, self.next.fetch_add(1, Ordering::Relaxed));
+            workers[i].in_flight += 1;
+            (i, workers[i].port, workers[i].generation)
+        };
+
+        let result = exchange(port, request, Instant::now() + self.timeout);
+
+        let mut workers = self.workers.lock().unwrap();
+        let worker = &mut workers[i];
+        worker.in_flight -= 1;
+        if let Err(err) = &result {
+            // Other requests may have timed out on the same process already,
+            // in which case it was restarted.
+            if err.kind() == io::ErrorKind::TimedOut && worker.generation == generation {
+                worker.killed = generation;
+                worker.restart(&self.exe, "request exceeded deadline");
+            }
+        }
+        // A worker which crashed by itself may have been replaced by the
+        // monitor in the meantime too, so a changed generation isn't enough.
+        let killed = worker.generation != generation && worker.killed == generation;
+        (result, killed)
+    }
+
+    fn stats(&self) -> String {
+        let workers = self.workers.lock().unwrap();
+        serde_json::json!({
+            "workers": workers
+                .iter()
+                .map(|w| serde_json::json!({
+                    "port": w.port,
+                    "pid": w.child.as_ref().map(|c| c.id()),
+                    "in_flight": w.in_flight,
+                    "restarts": w.restarts,
+                }))
+                .collect::<Vec<_>>(),
+            "restarts": workers.iter().map(|w| w.restarts).sum::<u64>(),
+            "tripped": self.breakers.lock().unwrap().reasons(Instant::now()),
+        })
+        .to_string()
+    }
+
+    // metrics returns the metrics in the Prometheus text format.
+    fn metrics(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            out.push_str(&format!("# HELP syntect_server_{} {}\n", name, help));
+            out.push_str(&format!("# TYPE syntect_server_{} {}\n", name, kind));
+            for (labels, value) in samples {
+                out.push_str(&format!("syntect_server_{}{} {}\n", name, labels, value));
+            }
+        };
+        {
+            let metrics = self.metrics.lock().unwrap();
+            metric(
+                "requests_total",
+                "counter",
+                "Requests handled by the supervisor, by status code.",
+                metrics
+                    .responses
+                    .iter()
+                    .map(|(code, n)| (format!("{{code=\"{}\"}}", code), n.to_string()))
+                    .collect(),
+            );
+            metric(
+                "request_duration_seconds_total",
+                "counter",
+                "Time spent handling requests.",
+                vec![(String::new(), metrics.seconds.to_string())],
+            );
+            metric(
+                "rejected_connections_total",
+                "counter",
+                "Connections turned away because too many were open.",
+                vec![(String::new(), metrics.rejected.to_string())],
+            );
+        }
+        metric(
+            "connections",
+            "gauge",
+            "Open client connections.",
+            vec![(
+                String::new(),
+                self.connections.load(Ordering::SeqCst).to_string(),
+            )],
+        );
+        let workers = self.workers.lock().unwrap();
+        metric(
+            "worker_in_flight_requests",
+            "gauge",
+            "Requests being handled by each worker.",
+            workers
+                .iter()
+                .map(|w| (format!("{{port=\"{}\"}}", w.port), w.in_flight.to_string()))
+                .collect(),
+        );
+        metric(
+            "worker_restarts_total",
+            "counter",
+            "Times each worker was restarted because it hung or exited.",
+            workers
+                .iter()
+                .map(|w| (format!("{{port=\"{}\"}}", w.port), w.restarts.to_string()))
+                .collect(),
+        );
+        out
+    }
+}
+
+// highlight_query returns the query of a request to highlight code, if it is
+// one whose language can be told. The cells of a notebook may be in different
+// languages, so the breakers of those are left to the workers.
+fn highlight_query(request: &Request) -> Option<Query> {
+    if request.method != "POST" || request.path != "/" {
+        return None;
+    }
+    let q: Query = serde_json::from_slice(&request.body).ok()?;
+    Some(q).filter(|q| !q.notebook)
+}
+
+// least_busy returns the index of the worker with the fewest requests in
+// flight. Ties go to the first such worker from start onwards, so that idle
+// workers take turns.
+fn least_busy(in_flight: &[usize], start: usize) -> usize {
+    let n = in_flight.len();
+    let mut best = start % n;
+    for k in 1..n {
+        let i = (start + k) % n;
+        if in_flight[i] < in_flight[best] {
+            best = i;
+        }
+    }
+    best
+}
+
+// exchange sends the request to the worker listening on port, and reads its
+// response until the worker closes the connection. It fails with TimedOut if
+// the worker doesn't respond before the deadline.
+fn exchange(port: u16, request: &Request, deadline: Instant) -> io::Result<Vec<u8>> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    // A worker which was just (re)started may still be loading its syntaxes.
+    // Waiting for it must not count as a timeout, or it would be restarted
+    // over and over again.
+    let mut worker = loop {
+        let not_ready = || io::Error::new(io::ErrorKind::ConnectionRefused, "worker not ready");
+        let timeout = remaining(deadline).map_err(|_| not_ready())?;
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => break stream,
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                thread::sleep(Duration::from_millis(50))
+            }
+            Err(err) => return Err(err),
+        }
+    };
+
+    worker.set_write_timeout(Some(remaining(deadline)?))?;
+    worker.write_all(&request.head)?;
+    worker.write_all(&request.body)?;
+
+    let mut response = Vec::new();
+    let mut buf = [0; 16 * 1024];
+    loop {
+        worker.set_read_timeout(Some(remaining(deadline)?))?;
+        match worker.read(&mut buf) {
+            // A worker which is killed, e.g. by the OOM killer, closes the
+            // connection without responding.
+            Ok(0) if response.is_empty() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "worker closed the connection without responding",
+                ))
+            }
+            Ok(0) => return Ok(response),
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline exceeded"))
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn remaining(deadline: Instant) -> io::Result<Duration> {
+    let now = Instant::now();
+    if now >= deadline {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline exceeded"));
+    }
+    Ok(deadline - now)
+}
+
+// read_request reads an HTTP/1.1 request with an optional Content-Length body
+// of at most max_body bytes from the client, which must send all of it before
+// the deadline.
+fn read_request(
+    client: &mut TcpStream,
+    max_body: usize,
+    deadline: Instant,
+) -> Result<Request, RequestError> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 16 * 1024];
+    let head_len = loop {
+        if let Some(i) = find(&buf, b"\r\n\r\n") {
+            break i + 4;
+        }
+        if buf.len() > MAX_HEAD_BYTES {
+            return Err(invalid("request head too large").into());
+        }
+        match read_before(client, &mut chunk, deadline)? {
+            0 => return Err(invalid("connection closed before end of request head").into()),
+            n => buf.extend_from_slice(&chunk[..n]),
+        }
+    };
+
+    let (mut request, content_length) = parse_head(&buf[..head_len])?;
+    if content_length > max_body {
+        return Err(RequestError::TooLarge(content_length));
+    }
+    request.body = buf.split_off(head_len);
+    while request.body.len() < content_length {
+        match read_before(client, &mut chunk, deadline)? {
+            0 => return Err(invalid("connection closed before end of request body").into()),
+            n => request.body.extend_from_slice(&chunk[..n]),
+        }
+    }
+    request.body.truncate(content_length);
+    Ok(request)
+}
+
+// read_before reads from the stream, failing with TimedOut at the deadline.
+// The read timeout applies to each read, so it is set to the time left every
+// time, or a client sending a byte at a time could hold on to the connection
+// forever.
+fn read_before(stream: &mut TcpStream, buf: &mut [u8], deadline: Instant) -> io::Result<usize> {
+    loop {
+        stream.set_read_timeout(Some(remaining(deadline)?))?;
+        match stream.read(buf) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+// parse_head parses the request line and headers of a request, and returns
+// the request to send to the worker along with the length of its body.
+// Workers serve a single request per connection.
+fn parse_head(head: &[u8]) -> io::Result<(Request, usize)> {
+    let head = std::str::from_utf8(head).map_err(|_| invalid("request head is not UTF-8"))?;
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or("");
+    let mut parts = request_line.split(' ');
+    let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(_)) if !method.is_empty() => (method, path),
+        _ => return Err(invalid("malformed request line")),
+    };
+
+    let mut forward = format!("{}\r\n", request_line);
+    let mut content_length = 0;
+    for line in lines.filter(|line| !line.is_empty()) {
+        let colon = line.find(':').ok_or_else(|| invalid("malformed header"))?;
+        let name = line[..colon].trim().to_ascii_lowercase();
+        let value = line[colon + 1..].trim();
+        match name.as_str() {
+            "content-length" => {
+                content_length = value
+                    .parse()
+                    .map_err(|_| invalid("invalid Content-Length"))?
+            }
+            "transfer-encoding" => return Err(invalid("chunked request bodies are not supported")),
+            "connection" | "keep-alive" | "proxy-connection" => continue,
+            _ => {}
+        }
+        forward.push_str(line);
+        forward.push_str("\r\n");
+    }
+    forward.push_str("Connection: close\r\n\r\n");
+
+    let request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        head: forward.into_bytes(),
+        body: Vec::new(),
+    };
+    Ok((request, content_length))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn http_response(status: u16, reason: &str, content_type: &str, body: &str) -> Vec<u8> {
+    format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        content_type,
+        body.len(),
+        body
+    )
+    .into_bytes()
+}
+
+fn error_response(status: u16, reason: &str, error: &str, code: &str) -> Vec<u8> {
+    let body = serde_json::json!({"error": error, "code": code}).to_string();
+    http_response(status, reason, "application/json", &body)
+}
+
+// status returns the status code of an HTTP response, or 0 if it has none,
+// e.g. because the worker's response was cut short.
+fn status(response: &[u8]) -> u16 {
+    response
+        .get(9..12)
+        .and_then(|code| std::str::from_utf8(code).ok())
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{least_busy, parse_head, read_request, Request, RequestError, Supervisor};
+    use crate::breaker::Breakers;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use syntect::parsing::SyntaxSet;
+
+    // read_from sends the request to read_request in pieces, pausing between
+    // them.
+    fn read_from(
+        pieces: Vec<&'static [u8]>,
+        pause: Duration,
+        max_body: usize,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, RequestError> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            for piece in pieces {
+                if stream.write_all(piece).is_err() {
+                    return;
+                }
+                thread::sleep(pause);
+            }
+        });
+        let (mut stream, _) = listener.accept().unwrap();
+        let result = read_request(&mut stream, max_body, Instant::now() + timeout);
+        drop(stream);
+        client.join().unwrap();
+        result.map(|request| request.body)
+    }
+
+    #[test]
+    fn least_busy_takes_turns() {
+        assert_eq!(least_busy(&[0, 0, 0], 0), 0);
+        assert_eq!(least_busy(&[0, 0, 0], 1), 1);
+        assert_eq!(least_busy(&[0, 0, 0], 5), 2);
+        assert_eq!(least_busy(&[1, 2, 0], 0), 2);
+        assert_eq!(least_busy(&[1, 0, 0, 1], 3), 1);
+        assert_eq!(least_busy(&[3], 7), 0);
+    }
+
+    #[test]
+    fn parse_head_forwards_one_request_per_connection() {
+        let head = b"POST / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\nContent-Type: application/json\r\nContent-Length: 42\r\n\r\n";
+        let (request, content_length) = parse_head(head).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/");
+        assert_eq!(content_length, 42);
+        assert_eq!(
+            String::from_utf8(request.head).unwrap(),
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: 42\r\nConnection: close\r\n\r\n"
+        );
+
+        let (request, content_length) = parse_head(b"GET /workers HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.path, "/workers");
+        assert_eq!(content_length, 0);
+
+        assert!(parse_head(b"garbage\r\n\r\n").is_err());
+        assert!(parse_head(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n").is_err());
+        assert!(parse_head(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn read_request_limits_body_and_time() {
+        let second = Duration::from_secs(1);
+        let body = read_from(
+            vec![b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel", b"lo"],
+            Duration::from_millis(10),
+            5,
+            second,
+        );
+        assert_eq!(body.unwrap(), b"hello");
+
+        // The body is not read at all when it is too large.
+        let result = read_from(
+            vec![b"POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\n"],
+            Duration::from_millis(0),
+            5,
+            second,
+        );
+        assert!(
+            matches!(result, Err(RequestError::TooLarge(6))),
+            "{:?}",
+            result
+        );
+
+        // A client sending a byte at a time must send the whole request in
+        // time, not just each byte.
+        let result = read_from(
+            vec![b"P"; 20],
+            Duration::from_millis(50),
+            5,
+            Duration::from_millis(300),
+        );
+        assert!(
+            matches!(result, Err(RequestError::TimedOut)),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn breakers_record_workers_killed_mid_request() {
+        // The fake worker hangs on the first request and dies during the
+        // second one, and isn't there for the third.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let worker = thread::spawn(move || {
+            let mut hung = Vec::new();
+            for hang in &[true, false] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let _ = stream.read(&mut [0; 1024]);
+                if *hang {
+                    hung.push(stream);
+                }
+            }
+        });
+
+        let supervisor = Supervisor::new(
+            "/bin/true".into(),
+            vec![port],
+            Duration::from_millis(300),
+            Duration::from_secs(1),
+            1,
+            Breakers::new(2, Duration::from_secs(60), Duration::from_secs(300)),
+        );
+        let request = || post("a.py");
+        let response = |request: &Request| String::from_utf8(supervisor.forward(request)).unwrap();
+
+        let hung = response(&request());
+        assert!(hung.starts_with("HTTP/1.1 504 "), "{}", hung);
+        let killed = response(&request());
+        assert!(killed.starts_with("HTTP/1.1 502 "), "{}", killed);
+        worker.join().unwrap();
+
+        // The breaker of Python tripped, so the supervisor answers with plain
+        // text itself rather than waiting for the worker.
+        let plaintext = response(&request());
+        assert!(plaintext.starts_with("HTTP/1.1 200 "), "{}", plaintext);
+        assert!(plaintext.contains("\"plaintext\":true"), "{}", plaintext);
+        assert!(
+            plaintext.contains("highlighting Python is disabled"),
+            "{}",
+            plaintext
+        );
+        assert!(plaintext.contains("(last: crash)"), "{}", plaintext);
+    }
+
+    #[test]
+    fn breakers_ignore_requests_failing_on_workers_killed_for_others() {
+        // The fake worker hangs on both requests, and dies once the
+        // supervisor gave up on the first one.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (accepted, first_accepted) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            let (mut first, _) = listener.accept().unwrap();
+            accepted.send(()).unwrap();
+            let (second, _) = listener.accept().unwrap();
+            while first.read(&mut [0; 1024]).map_or(false, |n| n > 0) {}
+            drop(second);
+        });
+
+        let supervisor = Arc::new(Supervisor::new(
+            "/bin/true".into(),
+            vec![port],
+            Duration::from_millis(500),
+            Duration::from_secs(1),
+            2,
+            Breakers::new(1, Duration::from_secs(60), Duration::from_secs(300)),
+        ));
+        let forward = |filepath: &'static str| {
+            let supervisor = supervisor.clone();
+            thread::spawn(move || String::from_utf8(supervisor.forward(&post(filepath))).unwrap())
+        };
+        let slow = forward("a.py");
+        first_accepted.recv().unwrap();
+        thread::sleep(Duration::from_millis(200));
+        let other = forward("a.rb");
+
+        let slow = slow.join().unwrap();
+        assert!(slow.starts_with("HTTP/1.1 504 "), "{}", slow);
+        let other = other.join().unwrap();
+        assert!(other.starts_with("HTTP/1.1 503 "), "{}", other);
+        assert!(other.contains("worker_restarted"), "{}", other);
+        worker.join().unwrap();
+
+        let breakers = supervisor.breakers.lock().unwrap();
+        assert!(breakers.reason("Python", Instant::now()).is_some());
+        assert_eq!(breakers.reason("Ruby", Instant::now()), None);
+    }
+
+    // post returns a request to highlight a line of code in the file.
+    fn post(filepath: &str) -> Request {
+        let body = format!(
+            r#"{{"filepath": "{}", "code": "x = 1\n", "css": true, "theme": ""}}"#,
+            filepath
+        );
+        Request {
+            method: "POST".to_string(),
+            path: "/".to_string(),
+            head: format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes(),
+            body: body.into_bytes(),
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::SYNTAX_SET;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use syntect::{
+    highlighting::ScopeSelectors,
+    parsing::{ParseState, Scope, ScopeStack, SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+const USAGE: &str = "usage: syntect_server syntax-test PATH...";
+
+/// Outcome is the result of running a single syntax test file.
+#[derive(Debug)]
+pub struct Outcome {
+    pub syntax: String,
+    pub assertions: usize,
+    pub failures: Vec<String>,
+}
+
+/// run implements the `syntax-test` subcommand.
+///
+/// Sublime grammars ship `syntax_test_*` files, whose comments assert the
+/// scopes of the line above them:
+///
+/// ```text
+/// // SYNTAX TEST "Packages/Go/Go.sublime-syntax"
+/// package main
+/// // <- keyword.other.package
+/// //      ^^^^ variable.other
+/// ```
+///
+/// It finds all syntax test files in the given files or directories (e.g. a
+/// checkout of our Packages fork), runs them against the syntaxes we ship, and
+/// reports the failing assertions per language. It exits with a non-zero
+/// status if any assertion failed.
+pub fn run(args: &[String]) -> i32 {
+    if args.is_empty() {
+        eprintln!("{}", USAGE);
+        return 2;
+    }
+    let mut files = Vec::new();
+    for arg in args {
+        if let Err(err) = find_tests(Path::new(arg), &mut files) {
+            eprintln!("{}: {}", arg, err);
+            return 1;
+        }
+    }
+    files.sort();
+
+    // Per language: (files, assertions, failures)
+    let mut languages: BTreeMap<String, (usize, usize, usize)> = BTreeMap::new();
+    let mut failed = false;
+    SYNTAX_SET.with(|ss| {
+        for file in &files {
+            match run_file(ss, file) {
+                Ok(outcome) => {
+                    let entry = languages.entry(outcome.syntax).or_default();
+                    entry.0 += 1;
+                    entry.1 += outcome.assertions;
+                    entry.2 += outcome.failures.len();
+                    for failure in &outcome.failures {
+                        println!("{}:{}", file.display(), failure);
+                    }
+                    failed |= !outcome.failures.is_empty();
+                }
+                Err(err) => {
+                    println!("{}: {}", file.display(), err);
+                    failed = true;
+                }
+            }
+        }
+    });
+
+    println!();
+    for (language, (files, assertions, failures)) in &languages {
+        println!(
+            "{:<32} {:>4} files {:>6} assertions {:>6} failed",
+            language, files, assertions, failures
+        );
+    }
+    if failed {
+        1
+    } else {
+        0
+    }
+}
+
+fn find_tests(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            find_tests(&entry?.path(), files)?;
+        }
+    } else if path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map_or(false, |n| n.starts_with("syntax_test"))
+    {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+/// run_file runs the assertions of a single syntax test file.
+pub fn run_file(ss: &SyntaxSet, path: &Path) -> Result<Outcome, String> {
+    let code = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let header = code.lines().next().unwrap_or("");
+    let (comment, syntax_path) = parse_header(header).ok_or("missing SYNTAX TEST header")?;
+    let sr = find_syntax(ss, syntax_path, path)
+        .ok_or_else(|| format!("no syntax found for {:?}", syntax_path))?;
+    Ok(run_test(ss, sr, comment, &code))
+}
+
+// parse_header parses the first line of a syntax test, e.g.
+// `// SYNTAX TEST "Packages/Go/Go.sublime-syntax"`, into the comment token
+// and the path of the syntax to test.
+fn parse_header(header: &str) -> Option<(&str, &str)> {
+    let i = header.find("SYNTAX TEST")?;
+    let comment = header[..i].trim();
+    let rest = &header[i + "SYNTAX TEST".len()..];
+    let start = rest.find('"')? + 1;
+    let end = start + rest[start..].find('"')?;
+    if comment.is_empty() {
+        return None;
+    }
+    Some((comment, &rest[start..end]))
+}
+
+fn find_syntax<'a>(
+    ss: &'a SyntaxSet,
+    syntax_path: &str,
+    test_path: &Path,
+) -> Option<&'a SyntaxReference> {
+    // The syntax dump doesn't know where the syntaxes were loaded from, so
+    // find them by name, e.g. "Go" for "Packages/Go/Go.sublime-syntax", and
+    // fall back to the extension of the test file.
+    let name = Path::new(syntax_path).file_stem()?.to_str()?;
+    ss.find_syntax_by_name(name).or_else(|| {
+        let extension = test_path.extension()?.to_str()?;
+        ss.find_syntax_by_extension(extension)
+    })
+}
+
+/// run_test parses code, which is a syntax test with the given comment token,
+/// and checks every assertion against the scopes of the line it refers to.
+pub fn run_test(ss: &SyntaxSet, sr: &SyntaxReference, comment: &str, code: &str) -> Outcome {
+    let mut outcome = Outcome {
+        syntax: sr.name.clone(),
+        assertions: 0,
+        failures: Vec::new(),
+    };
+    let mut parse_state = ParseState::new(sr);
+    let mut stack = ScopeStack::new();
+    // The scopes at every byte of the last line which wasn't an assertion.
+    let mut tested: Vec<Vec<Scope>> = Vec::new();
+    let mut tested_line = 0;
+
+    for (i, line) in LinesWithEndings::from(code).enumerate() {
+        let ops = parse_state.parse_line(line, ss);
+        let assertion = if i == 0 {
+            None
+        } else {
+            parse_assertion(line, comment)
+        };
+        if assertion.is_none() {
+            tested.clear();
+            tested_line = i;
+        }
+
+        // Record the scope stack at every byte of the line, unless it is an
+        // assertion, since those refer to the line above them.
+        let mut ops = ops.iter();
+        let mut next = ops.next();
+        for (col, _) in line.char_indices() {
+            while let Some((at, op)) = next {
+                if *at > col {
+                    break;
+                }
+                stack.apply(op);
+                next = ops.next();
+            }
+            if assertion.is_none() {
+                while tested.len() < col {
+                    let fill = tested.last().cloned().unwrap_or_default();
+                    tested.push(fill);
+                }
+                tested.push(stack.as_slice().to_vec());
+            }
+        }
+        for (_, op) in next.into_iter().chain(ops) {
+            stack.apply(op);
+        }
+
+        if let Some((columns, selector)) = assertion {
+            outcome.assertions += 1;
+            if let Err(failure) = check_assertion(&tested, &columns, selector) {
+                outcome
+                    .failures
+                    .push(format!("{}:{}", tested_line + 1, failure));
+            }
+        }
+    }
+    outcome
+}
+
+// check_assertion checks that the scopes at all columns match selector,
+// returning a description of the first column which doesn't.
+fn check_assertion(tested: &[Vec<Scope>], columns: &[usize], selector: &str) -> Result<(), String> {
+    let selectors = ScopeSelectors::from_str(selector)
+        .map_err(|err| format!(" invalid selector {:?}: {:?}", selector, err))?;
+    for &col in columns {
+        let scopes = tested.get(col).map_or(&[][..], |s| s.as_slice());
+        if selectors.does_match(scopes).is_none() {
+            return Err(format!(
+                "{}: expected {:?}, got [{}]",
+                col + 1,
+                selector,
+                scopes
+                    .iter()
+                    .map(|s| s.build_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+// parse_assertion parses an assertion line such as `//   ^^^ string.quoted`
+// or `// <- keyword`, returning the columns it asserts and its selector.
+fn parse_assertion<'a>(line: &'a str, comment: &str) -> Option<(Vec<usize>, &'a str)> {
+    let start = line.find(comment)?;
+    if !line[..start].trim().is_empty() {
+        return None;
+    }
+    let body_start = start + comment.len();
+    let body = &line[body_start..];
+    let trimmed = body.trim_start();
+    let offset = body_start + body.len() - trimmed.len();
+    let (columns, rest) = if let Some(rest) = trimmed.strip_prefix("<-") {
+        (vec![start], rest)
+    } else if trimmed.starts_with('^') {
+        let carets = trimmed.len() - trimmed.trim_start_matches('^').len();
+        ((offset..offset + carets).collect(), &trimmed[carets..])
+    } else {
+        return None;
+    };
+    // Block comments, e.g. `<!-- ^ entity.name.tag -->`, end with a token too.
+    let selector = rest.trim();
+    let selector = match selector.rfind(' ') {
+        Some(i) if ["-->", "*/", "*)", "-}"].contains(&&selector[i + 1..]) => selector[..i].trim(),
+        _ => selector,
+    };
+    Some((columns, selector))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_assertion, parse_header, run_file};
+    use std::fs;
+    use syntect::parsing::SyntaxSet;
+
+    const SYNTAX_TESTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/syntax_tests");
+
+    #[test]
+    fn parses_headers_and_assertions() {
+        assert_eq!(
+            parse_header("// SYNTAX TEST \"Packages/Go/Go.sublime-syntax\""),
+            Some(("//", "Packages/Go/Go.sublime-syntax"))
+        );
+        assert_eq!(
+            parse_header("<!-- SYNTAX TEST \"Packages/HTML/HTML.sublime-syntax\" -->"),
+            Some(("<!--", "Packages/HTML/HTML.sublime-syntax"))
+        );
+        assert_eq!(parse_header("package main"), None);
+
+        assert_eq!(
+            parse_assertion("// <- keyword.other\n", "//"),
+            Some((vec![0], "keyword.other"))
+        );
+        assert_eq!(
+            parse_assertion("//   ^^^ string.quoted - comment\n", "//"),
+            Some((vec![5, 6, 7], "string.quoted - comment"))
+        );
+        assert_eq!(
+            parse_assertion("<!-- ^ entity.name.tag -->\n", "<!--"),
+            Some((vec![5], "entity.name.tag"))
+        );
+        assert_eq!(parse_assertion("// a comment\n", "//"), None);
+        assert_eq!(parse_assertion("x // ^ not an assertion\n", "//"), None);
+    }
+
+    #[test]
+    fn bundled_syntax_tests_pass() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        for entry in fs::read_dir(SYNTAX_TESTS).unwrap() {
+            let path = entry.unwrap().path();
+            let outcome = run_file(&ss, &path).unwrap();
+            assert!(outcome.assertions > 0, "{}", path.display());
+            assert!(
+                outcome.failures.is_empty(),
+                "{}:\n{}",
+                path.display(),
+                outcome.failures.join("\n")
+            );
+        }
+    }
+
+    #[test]
+    fn failing_assertions_are_reported() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let sr = ss.find_syntax_by_extension("go").unwrap();
+        let code = "// SYNTAX TEST \"Packages/Go/Go.sublime-syntax\"\n\
+                    package main\n\
+                    // <- string.quoted\n\
+                    //      ^^^^ variable.other\n";
+        let outcome = super::run_test(&ss, sr, "//", code);
+        assert_eq!(outcome.assertions, 2);
+        assert_eq!(outcome.failures.len(), 1, "{:?}", outcome.failures);
+        assert!(outcome.failures[0].starts_with("2:1: expected \"string.quoted\""));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::css_table::{self, InlineTableStyle};
+use crate::engine::{self, Engine};
+use syntect::{
+    highlighting::{Color, HighlightIterator, HighlightState, Highlighter, Style, Theme},
+    html::{start_highlighted_html_snippet, styled_line_to_highlighted_html, IncludeBackground},
+    parsing::{ScopeStack, SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+/// highlighted_html_for_string is syntect's function of the same name, which
+/// renders code as a `<pre>` with inline theme colors, except that lines longer
+/// than max_line_len are not highlighted, like in `ClassedTableGenerator`, and
+/// that code blocks in markup are highlighted, see `code_blocks`.
+///
+/// Such lines are emitted escaped in the theme's default style, and are not
+/// fed to the parser, so the following lines are highlighted as if the long
+/// line wasn't there.
+pub fn highlighted_html_for_string(
+    s: &str,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    max_line_len: Option<usize>,
+) -> String {
+    let (mut output, _) = start_highlighted_html_snippet(theme);
+    highlight_lines(s, ss, syntax, theme, max_line_len, |_, html| {
+        output.push_str(&html)
+    });
+    output.push_str("</pre>\n");
+    output
+}
+
+/// highlighted_table_for_string renders code as the same table as
+/// `ClassedTableGenerator`, but with inline theme colors instead of classes
+/// and with the line numbers as text, so that it renders without our
+/// stylesheet, e.g. in emails and exports.
+pub fn highlighted_table_for_string(
+    s: &str,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    max_line_len: Option<usize>,
+) -> String {
+    let style = table_style(theme);
+    let mut output = String::with_capacity(s.len() * 8);
+    css_table::open_table_with_style(&mut output, Some(&style));
+    highlight_lines(s, ss, syntax, theme, max_line_len, |i, html| {
+        css_table::open_row_with_style(&mut output, i, Some(&style));
+        output.push_str(&html);
+        css_table::close_row(&mut output);
+    });
+    css_table::close_table(&mut output);
+    output
+}
+
+// highlight_lines calls f with the index and the highlighted HTML of every
+// line of the code. The code is parsed like in CSS mode, so that code blocks
+// in markup are highlighted too, except that it is always parsed by syntect.
+fn highlight_lines(
+    s: &str,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    max_line_len: Option<usize>,
+    mut f: impl FnMut(usize, String),
+) {
+    let highlighter = Highlighter::new(theme);
+    let mut highlight_state = HighlightState::new(&highlighter, ScopeStack::new());
+    let default_style = highlighter.get_default();
+    let bg = background(theme);
+    let parsed_lines = engine::parse(Engine::Syntect, s, ss, syntax, max_line_len);
+    for (i, (line, ops)) in LinesWithEndings::from(s).zip(parsed_lines).enumerate() {
+        let html = match ops {
+            Some(ops) => {
+                let regions: Vec<(Style, &str)> =
+                    HighlightIterator::new(&mut highlight_state, &ops, line, &highlighter)
+                        .collect();
+                styled_line_to_highlighted_html(&regions[..], IncludeBackground::IfDifferent(bg))
+            }
+            None => styled_line_to_highlighted_html(
+                &[(default_style, line)],
+                IncludeBackground::IfDifferent(bg),
+            ),
+        };
+        f(i, html);
+    }
+}
+
+fn background(theme: &Theme) -> Color {
+    theme.settings.background.unwrap_or(Color::WHITE)
+}
+
+fn table_style(theme: &Theme) -> InlineTableStyle {
+    let foreground = theme.settings.foreground.unwrap_or(Color::BLACK);
+    let gutter_foreground = theme.settings.gutter_foreground.unwrap_or(foreground);
+    let gutter = theme.settings.gutter.unwrap_or_else(|| background(theme));
+    InlineTableStyle {
+        table: format!(
+            "background-color:{};color:{};border-collapse:collapse;",
+            hex(background(theme)),
+            hex(foreground)
+        ),
+        line: format!(
+            "background-color:{};color:{};text-align:right;vertical-align:top;padding:0 1em 0 0.5em;user-select:none;",
+            hex(gutter),
+            hex(gutter_foreground)
+        ),
+        code: "white-space:pre;font-family:monospace;".to_string(),
+    }
+}
+
+fn hex(c: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{highlighted_html_for_string, highlighted_table_for_string};
+    use syntect::{highlighting::ThemeSet, html, parsing::SyntaxSet};
+
+    #[test]
+    fn matches_syntect_without_limit() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let theme = &ts.themes["InspiredGitHub"];
+        let sr = ss.find_syntax_by_extension("go").unwrap();
+        let code = "package main\n\nfunc main() {\n\tprintln(\"<hi>\")\n}\n";
+        assert_eq!(
+            highlighted_html_for_string(code, &ss, sr, theme, None),
+            html::highlighted_html_for_string(code, &ss, sr, theme)
+        );
+    }
+
+    #[test]
+    fn long_lines_are_not_highlighted() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let theme = &ts.themes["InspiredGitHub"];
+        let sr = ss.find_syntax_by_extension("js").unwrap();
+        let long = format!("var s = \"{}<&>\";\n", "x".repeat(100));
+        let code = format!("{}var n = 1;\n", long);
+
+        let limited = highlighted_html_for_string(&code, &ss, sr, theme, Some(50));
+        // The long line is escaped, in a single span of the default style.
+        let escaped = format!("var s = &quot;{}&lt;&amp;&gt;&quot;;\n", "x".repeat(100));
+        assert!(
+            limited.contains(&format!("\">{}</span>", escaped)),
+            "{}",
+            limited
+        );
+
+        // The following line is highlighted as if the long line wasn't there.
+        let rest = highlighted_html_for_string("var n = 1;\n", &ss, sr, theme, None);
+        let rest = rest.splitn(2, '\n').nth(1).unwrap();
+        assert!(limited.ends_with(rest), "{}", limited);
+    }
+
+    #[test]
+    fn table_has_numbered_rows_with_inline_styles() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let theme = &ts.themes["InspiredGitHub"];
+        let sr = ss.find_syntax_by_extension("go").unwrap();
+        let code = "package main\n\nfunc main() {}\n";
+
+        let table = highlighted_table_for_string(code, &ss, sr, theme, None);
+        assert!(
+            table.starts_with("<table style=\"background-color:#ffffff;"),
+            "{}",
+            table
+        );
+        assert!(
+            table.ends_with("</div></td></tr></tbody></table>"),
+            "{}",
+            table
+        );
+        assert_eq!(table.matches("<tr>").count(), 3);
+        for n in 1..=3 {
+            assert!(
+                table.contains(&format!("<td class=\"line\" data-line=\"{0}\" style=\"", n)),
+                "{}",
+                table
+            );
+            assert!(table.contains(&format!(";\">{}</td>", n)), "{}", table);
+        }
+        assert!(table.contains("<span style=\"color:"), "{}", table);
+        assert!(!table.contains("class=\"hl-"), "{}", table);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::engine::{net_ops, ParsedLines};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use syntect::{
+    parsing::{Scope, ScopeStackOp, SyntaxReference},
+    util::LinesWithEndings,
+};
+use tree_sitter::Language;
+use tree_sitter_highlight::{Highlight, HighlightConfiguration, HighlightEvent, Highlighter};
+
+// CAPTURES maps the capture names of tree-sitter highlight queries to the
+// scopes they are rendered as, which get the language's atom appended just
+// like the scopes of syntect grammars. Output in every format (class names,
+// semantic tokens, occurrences) is then derived from the scopes as usual.
+const CAPTURES: &[(&str, &str)] = &[
+    ("attribute", "entity.other.attribute-name"),
+    ("comment", "comment"),
+    ("constant", "constant.other"),
+    ("constant.builtin", "constant.language"),
+    ("constructor", "variable.function.constructor"),
+    ("embedded", "meta.embedded"),
+    ("escape", "constant.character.escape"),
+    ("function", "variable.function"),
+    ("function.builtin", "support.function"),
+    ("function.macro", "variable.function.macro"),
+    ("function.method", "variable.function.method"),
+    ("keyword", "keyword"),
+    ("label", "entity.name.label"),
+    ("module", "entity.name.namespace"),
+    ("number", "constant.numeric"),
+    ("operator", "keyword.operator"),
+    ("property", "variable.other.member"),
+    ("punctuation.bracket", "punctuation.section"),
+    ("punctuation.delimiter", "punctuation.separator"),
+    ("punctuation.special", "punctuation.section.interpolation"),
+    ("string", "string.quoted"),
+    ("string.special", "string.other"),
+    ("tag", "entity.name.tag"),
+    ("type", "storage.type"),
+    ("type.builtin", "support.type"),
+    ("variable", "variable.other"),
+    ("variable.builtin", "variable.language"),
+    ("variable.parameter", "variable.parameter"),
+];
+
+// Grammar is a compiled tree-sitter grammar and highlight query for a syntax.
+struct Grammar {
+    config: HighlightConfiguration,
+    // The scope wrapping the whole document, e.g. `source.go`.
+    root: Scope,
+    // The scope of each capture in CAPTURES.
+    scopes: Vec<Scope>,
+}
+
+lazy_static! {
+    // The grammars compiled in, by the name of the syntect syntax they
+    // replace.
+    static ref GRAMMARS: HashMap<&'static str, Grammar> = {
+        let mut grammars = HashMap::new();
+        let languages = vec![
+            (
+                "Go",
+                "go",
+                tree_sitter_go::language(),
+                tree_sitter_go::HIGHLIGHT_QUERY.to_string(),
+            ),
+            (
+                "Python",
+                "python",
+                tree_sitter_python::language(),
+                tree_sitter_python::HIGHLIGHT_QUERY.to_string(),
+            ),
+            (
+                "Rust",
+                "rust",
+                tree_sitter_rust::language(),
+                tree_sitter_rust::HIGHLIGHT_QUERY.to_string(),
+            ),
+            // The TypeScript and C++ queries only add to those of the
+            // languages they extend.
+            (
+                "TypeScript",
+                "ts",
+                tree_sitter_typescript::language_typescript(),
+                format!(
+                    "{}\n{}",
+                    tree_sitter_javascript::HIGHLIGHT_QUERY,
+                    tree_sitter_typescript::HIGHLIGHT_QUERY
+                ),
+            ),
+            (
+                "TypeScriptReact",
+                "tsx",
+                tree_sitter_typescript::language_tsx(),
+                format!(
+                    "{}\n{}",
+                    tree_sitter_javascript::HIGHLIGHT_QUERY,
+                    tree_sitter_typescript::HIGHLIGHT_QUERY
+                ),
+            ),
+            (
+                "C++",
+                "c++",
+                tree_sitter_cpp::language(),
+                format!(
+                    "{}\n{}",
+                    tree_sitter_cpp::HIGHLIGHT_QUERY,
+                    tree_sitter_c::HIGHLIGHT_QUERY
+                ),
+            ),
+        ];
+        for (name, atom, language, query) in languages {
+            match Grammar::new(atom, language, &query) {
+                Ok(grammar) => {
+                    grammars.insert(name, grammar);
+                }
+                Err(err) => eprintln!("tree-sitter grammar for {} is broken: {}", name, err),
+            }
+        }
+        grammars
+    };
+}
+
+thread_local! {
+    static HIGHLIGHTER: RefCell<Highlighter> = RefCell::new(Highlighter::new());
+}
+
+impl Grammar {
+    fn new(atom: &str, language: Language, query: &str) -> Result<Self, String> {
+        let mut config = HighlightConfiguration::new(language, query, "", "")
+            .map_err(|err| format!("{:?}", err))?;
+        let names: Vec<&str> = CAPTURES.iter().map(|(capture, _)| *capture).collect();
+        config.configure(&names);
+        let scope =
+            |s: &str| Scope::new(&format!("{}.{}", s, atom)).map_err(|err| format!("{:?}", err));
+        Ok(Grammar {
+            config,
+            root: scope("source")?,
+            scopes: CAPTURES
+                .iter()
+                .map(|(_, s)| scope(s))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// supports reports whether tree-sitter has a grammar for the syntax.
+pub fn supports(syntax: &str) -> bool {
+    GRAMMARS.contains_key(syntax)
+}
+
+/// parse returns the scope stack ops of every line of the code, as determined
+/// by tree-sitter, or None if it has no grammar for the syntax or failed.
+///
+/// Lines longer than max_line_len are not highlighted, like with syntect. As
+/// tree-sitter parses them anyway, their ops are reduced to their net effect
+/// on the scope stack, which is applied at the start of the next highlighted
+/// line, so that the following lines are highlighted correctly.
+pub fn parse(
+    code: &str,
+    syntax: &SyntaxReference,
+    max_line_len: Option<usize>,
+) -> Option<ParsedLines> {
+    let grammar = GRAMMARS.get(syntax.name.as_str())?;
+    let lines: Vec<&str> = LinesWithEndings::from(code).collect();
+    let mut ops = vec![Vec::new(); lines.len()];
+    if let Some(first) = ops.first_mut() {
+        first.push((0, ScopeStackOp::Push(grammar.root)));
+    }
+
+    HIGHLIGHTER.with(|highlighter| {
+        let mut highlighter = highlighter.borrow_mut();
+        let events = highlighter
+            .highlight(&grammar.config, code.as_bytes(), None, |_| None)
+            .ok()?;
+        // Events come in order, so the line of each is found by advancing
+        // from the line of the previous one.
+        let (mut line, mut line_start, mut pos) = (0, 0, 0);
+        for event in events {
+            let op = match event.ok()? {
+                HighlightEvent::Source { end, .. } => {
+                    pos = end;
+                    continue;
+                }
+                HighlightEvent::HighlightStart(Highlight(i)) => {
+                    ScopeStackOp::Push(grammar.scopes[i])
+                }
+                HighlightEvent::HighlightEnd => ScopeStackOp::Pop(1),
+            };
+            while line + 1 < lines.len() && pos >= line_start + lines[line].len() {
+                line_start += lines[line].len();
+                line += 1;
+            }
+            ops[line].push((pos - line_start, op));
+        }
+        Some(())
+    })?;
+
+    // The net ops of the long lines since the last highlighted line.
+    let mut carried = Vec::new();
+    Some(
+        lines
+            .iter()
+            .zip(ops)
+            .map(|(line, ops)| {
+                carried.extend(ops);
+                if max_line_len.map_or(false, |n| line.len() > n) {
+                    carried = net_ops(0, mem::take(&mut carried));
+                    None
+                } else {
+                    Some(mem::take(&mut carried))
+                }
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::css_table::{table_for_parsed_lines, ClassNames};
+    use crate::{html_validation, occurrences};
+    use syntect::parsing::SyntaxSet;
+
+    #[test]
+    fn go_table() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let sr = ss.find_syntax_by_extension("go").unwrap();
+        let code =
+            "package main\n\n// main prints a string.\nfunc main() {\n\tprintln(\"hi\")\n}\n";
+        let parsed_lines = parse(code, sr, None).unwrap();
+        let html = table_for_parsed_lines(code, &parsed_lines, ClassNames::Full { prefix: "hl-" });
+        html_validation::validate(&html, code).unwrap();
+        assert!(
+            html.contains("<span class=\"hl-source hl-go\">"),
+            "{}",
+            html
+        );
+        assert!(
+            html.contains("<span class=\"hl-keyword hl-go\">package</span>"),
+            "{}",
+            html
+        );
+        assert!(
+            html.contains("<span class=\"hl-comment hl-go\">// main prints a string.</span>"),
+            "{}",
+            html
+        );
+    }
+
+    #[test]
+    fn long_lines_keep_the_stack_balanced() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let sr = ss.find_syntax_by_extension("go").unwrap();
+        let code = "package main\n\nvar s = `a raw string\nspanning lines` + \"and a long line\"\nvar n = 1\n";
+        let limited = parse(code, sr, Some(30)).unwrap();
+        let unlimited = parse(code, sr, None).unwrap();
+        let html = table_for_parsed_lines(code, &limited, ClassNames::Full { prefix: "hl-" });
+        html_validation::validate(&html, code).unwrap();
+
+        // The long line is left as plain escaped text, without tokens.
+        assert!(limited[3].is_none());
+        let long = html.split("<tr>").nth(4).unwrap();
+        assert!(
+            long.contains("spanning lines` + &quot;and a long line&quot;"),
+            "{}",
+            long
+        );
+        assert!(!long.contains("<span"), "{}", long);
+        let occurrences = occurrences::occurrences(code, limited.clone().into_iter());
+        assert!(occurrences.iter().all(|o| o.line != 3), "{:?}", occurrences);
+
+        // The last line is highlighted as if there was no limit.
+        let last = |html: &str| html.rsplit("<tr>").next().unwrap().to_string();
+        let expected = table_for_parsed_lines(code, &unlimited, ClassNames::Full { prefix: "hl-" });
+        assert_eq!(last(&html), last(&expected));
+    }
+}
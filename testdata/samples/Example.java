@@ -0,0 +1,14 @@
+package com.example;
+
+import java.util.List;
+
+/** Javadoc for {@link Example}. */
+public final class Example<T extends Comparable<T>> {
+    private static final int LIMIT = 0x10;
+
+    @Override
+    public String toString() {
+        List<String> xs = List.of("a", "b");
+        return xs.stream().map(s -> s + '!').reduce("", String::concat);
+    }
+}
@@ -0,0 +1,12 @@
+#include <stdio.h>
+#define SQUARE(x) ((x) * (x))
+
+/* Block comment */
+static int counter = 0;
+
+int main(int argc, char *argv[]) {
+    for (int i = 0; i < argc; i++) {
+        printf("%d: %s\n", SQUARE(i), argv[i]);
+    }
+    return 0;
+}
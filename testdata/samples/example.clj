@@ -0,0 +1,9 @@
+(ns example.core
+  (:require [clojure.string :as str]))
+
+(defn greet
+  "Greets someone."
+  [name]
+  (str "Hello, " (str/capitalize name) "!"))
+
+(println (greet "world") {:a 1 :b [1 2 3]})
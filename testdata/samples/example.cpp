@@ -0,0 +1,19 @@
+#include <iostream>
+#include <vector>
+
+namespace demo {
+template <typename T>
+class Stack {
+public:
+    void push(const T& value) { items_.push_back(value); }
+    T pop() { auto v = items_.back(); items_.pop_back(); return v; }
+private:
+    std::vector<T> items_;
+};
+}  // namespace demo
+
+int main() {
+    demo::Stack<int> s;
+    s.push(42);
+    std::cout << s.pop() << std::endl;
+}
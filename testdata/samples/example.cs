@@ -0,0 +1,17 @@
+using System;
+using System.Linq;
+
+namespace Demo
+{
+    public record Person(string Name, int Age);
+
+    public static class Program
+    {
+        public static void Main(string[] args)
+        {
+            var people = new[] { new Person("Ada", 36) };
+            foreach (var p in people.Where(p => p.Age > 18))
+                Console.WriteLine($"{p.Name} is {p.Age}");
+        }
+    }
+}
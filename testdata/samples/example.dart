@@ -0,0 +1,9 @@
+import 'dart:math';
+
+class Point {
+  final double x, y;
+  const Point(this.x, this.y);
+  double get norm => sqrt(x * x + y * y);
+}
+
+void main() => print('norm: ${Point(3, 4).norm}');
@@ -0,0 +1,6 @@
+-module(example).
+-export([fact/1]).
+
+%% Factorial.
+fact(0) -> 1;
+fact(N) when N > 0 -> N * fact(N - 1).
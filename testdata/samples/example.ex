@@ -0,0 +1,11 @@
+defmodule Greeter do
+  @moduledoc "Greets people."
+
+  def greet(name) when is_binary(name) do
+    "Hello, #{name}!"
+  end
+
+  def greet(_), do: {:error, :invalid}
+end
+
+IO.puts(Greeter.greet("world"))
@@ -0,0 +1,15 @@
+package main
+
+import "fmt"
+
+// Greeter greets people.
+type Greeter struct{ Name string }
+
+func (g *Greeter) Greet(who string) string {
+	return fmt.Sprintf("Hello, %s! I'm %s.", who, g.Name)
+}
+
+func main() {
+	g := &Greeter{Name: "gopher"}
+	fmt.Println(g.Greet(`world`), 42, 3.14, 'x')
+}
@@ -0,0 +1,5 @@
+def greet(String name = 'world') {
+    println "Hello, ${name}!"
+}
+
+[1, 2, 3].each { greet(it.toString()) }
@@ -0,0 +1,13 @@
+#ifndef EXAMPLE_H
+#define EXAMPLE_H
+
+#include <stddef.h>
+
+typedef struct buffer {
+    char *data;
+    size_t len;
+} buffer;
+
+int buffer_append(buffer *b, const char *s, size_t n);
+
+#endif
@@ -0,0 +1,11 @@
+module Main where
+
+import Data.List (sortBy)
+
+-- | Quicksort.
+qsort :: Ord a => [a] -> [a]
+qsort [] = []
+qsort (p:xs) = qsort [x | x <- xs, x < p] ++ [p] ++ qsort [x | x <- xs, x >= p]
+
+main :: IO ()
+main = print $ qsort [3, 1, 2 :: Int]
@@ -0,0 +1,6 @@
+function fib(n::Int)
+    n < 2 && return n
+    return fib(n - 1) + fib(n - 2)
+end
+
+println("fib(10) = $(fib(10))")
@@ -0,0 +1,12 @@
+// A comment
+const re = /^[a-z]+\d*$/gi;
+
+export default async function fetchAll(urls) {
+  const results = await Promise.all(urls.map((u) => fetch(u).then((r) => r.json())));
+  return results.filter(Boolean).map(({ id, ...rest }) => ({ id: +id, ...rest }));
+}
+
+class Foo extends Bar {
+  static #count = 0;
+  get value() { return this._value ?? null; }
+}
@@ -0,0 +1,7 @@
+function App({ items }) {
+  return (
+    <ul>
+      {items.map((item) => <li key={item.id}>{item.label}</li>)}
+    </ul>
+  );
+}
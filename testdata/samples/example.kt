@@ -0,0 +1,13 @@
+package demo
+
+data class User(val name: String, val age: Int = 0)
+
+fun main(args: Array<String>) {
+    val users = listOf(User("Ada", 36), User("Linus"))
+    users.filter { it.age > 0 }
+        .forEach { println("${it.name} is ${it.age}") }
+    when (args.size) {
+        0 -> println("no args")
+        else -> println(args.joinToString())
+    }
+}
@@ -0,0 +1,13 @@
+local M = {}
+
+-- A comment
+function M.greet(name)
+  name = name or "world"
+  return string.format("Hello, %s!", name)
+end
+
+for i, v in ipairs({1, 2, 3}) do
+  print(i, v, M.greet())
+end
+
+return M
@@ -0,0 +1,11 @@
+#import <Foundation/Foundation.h>
+
+@interface Greeter : NSObject
+- (NSString *)greet:(NSString *)name;
+@end
+
+@implementation Greeter
+- (NSString *)greet:(NSString *)name {
+    return [NSString stringWithFormat:@"Hello, %@", name];
+}
+@end
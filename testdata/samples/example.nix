@@ -0,0 +1,6 @@
+{ pkgs ? import <nixpkgs> {} }:
+
+pkgs.mkShell {
+  buildInputs = [ pkgs.rustc pkgs.cargo ];
+  shellHook = "echo hello";
+}
@@ -0,0 +1,13 @@
+<?php
+namespace App\Http;
+
+use Illuminate\Support\Str;
+
+final class Controller extends BaseController
+{
+    public function show(int $id): string
+    {
+        $user = User::find($id) ?? throw new NotFound("no user {$id}");
+        return view('user', ['name' => Str::title($user->name)]);
+    }
+}
@@ -0,0 +1,10 @@
+#!/usr/bin/perl
+use strict;
+use warnings;
+
+my %counts;
+while (my $line = <STDIN>) {
+    chomp $line;
+    $counts{$_}++ for split /\s+/, $line;
+}
+printf "%-10s %d\n", $_, $counts{$_} for sort keys %counts;
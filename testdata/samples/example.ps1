@@ -0,0 +1,5 @@
+param([string]$Path = ".")
+
+Get-ChildItem -Path $Path -Recurse -Filter *.log |
+    Where-Object { $_.Length -gt 1MB } |
+    ForEach-Object { Write-Host "$($_.FullName): $($_.Length)" }
@@ -0,0 +1,11 @@
+import os
+from typing import List
+
+
+@decorator(arg=1)
+class Example(Base):
+    """Docstring."""
+
+    def method(self, xs: List[int], *args, **kwargs) -> int:
+        total = sum(x ** 2 for x in xs if x % 2 == 0)
+        return f"{total:>10} {os.sep!r}" if args else None
@@ -0,0 +1,5 @@
+# comment
+df <- data.frame(x = 1:10, y = rnorm(10))
+model <- lm(y ~ x, data = df)
+summary(model)
+plot(df$x, df$y, main = "Scatter")
@@ -0,0 +1,19 @@
+require "json"
+
+module Greeting
+  # A comment
+  class Greeter < Base
+    attr_reader :name
+
+    def initialize(name = "world")
+      @name = name
+    end
+
+    def greet(*others, loud: false)
+      msg = "Hello, #{[name, *others].join(', ')}"
+      loud ? msg.upcase : msg
+    end
+  end
+end
+
+puts Greeting::Greeter.new.greet(:sym, loud: true) if __FILE__ == $0
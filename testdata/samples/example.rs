@@ -0,0 +1,16 @@
+use std::collections::HashMap;
+
+/// Doc comment.
+#[derive(Debug, Clone)]
+pub struct Counter<'a> {
+    counts: HashMap<&'a str, usize>,
+}
+
+impl<'a> Counter<'a> {
+    pub fn add(&mut self, word: &'a str) -> usize {
+        let n = self.counts.entry(word).or_insert(0);
+        *n += 1;
+        println!("{} => {}", word, n);
+        *n
+    }
+}
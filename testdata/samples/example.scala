@@ -0,0 +1,8 @@
+object Main extends App {
+  case class Point(x: Int, y: Int)
+
+  def norm(p: Point): Double = math.sqrt(p.x * p.x + p.y * p.y)
+
+  val points = List(Point(1, 2), Point(3, 4))
+  points.map(norm).foreach(n => println(s"norm: $n"))
+}
@@ -0,0 +1,8 @@
+#!/bin/bash
+set -euo pipefail
+
+for f in "$@"; do
+  if [[ -f "$f" ]]; then
+    echo "$(wc -l < "$f") lines in ${f##*/}"
+  fi
+done
@@ -0,0 +1,9 @@
+-- Top customers
+SELECT c.id, c.name, SUM(o.total) AS revenue
+FROM customers c
+JOIN orders o ON o.customer_id = c.id
+WHERE o.created_at >= '2021-01-01'
+GROUP BY c.id, c.name
+HAVING SUM(o.total) > 1000
+ORDER BY revenue DESC
+LIMIT 10;
@@ -0,0 +1,13 @@
+import Foundation
+
+struct Point: Equatable {
+    var x: Double
+    var y: Double
+
+    func distance(to other: Point) -> Double {
+        return ((x - other.x) * (x - other.x) + (y - other.y) * (y - other.y)).squareRoot()
+    }
+}
+
+let p = Point(x: 1, y: 2)
+print("distance: \(p.distance(to: Point(x: 0, y: 0)))")
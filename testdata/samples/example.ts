@@ -0,0 +1,11 @@
+interface Point {
+  readonly x: number;
+  y?: number;
+}
+
+export function distance<T extends Point>(a: T, b: T = { x: 0 } as T): number {
+  const dy = (a.y ?? 0) - (b.y ?? 0);
+  return Math.sqrt((a.x - b.x) ** 2 + dy ** 2);
+}
+
+enum Color { Red = "red", Green = `green` }
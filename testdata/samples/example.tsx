@@ -0,0 +1,10 @@
+import React, { useState } from "react";
+
+export const Counter: React.FC<{ start?: number }> = ({ start = 0 }) => {
+  const [count, setCount] = useState(start);
+  return (
+    <button className="counter" onClick={() => setCount(count + 1)}>
+      Clicked {count} times &amp; counting
+    </button>
+  );
+};
@@ -0,0 +1,4 @@
+// SYNTAX TEST "Packages/Go/Go.sublime-syntax"
+package main
+// <- keyword.other.package
+//      ^^^^ variable.other
@@ -0,0 +1,14 @@
+// SYNTAX TEST "Packages/Java/Java.sublime-syntax"
+package com.lwl.boot.model;
+// <- keyword.other.package
+//      ^^^ entity.name.namespace
+//         ^ punctuation.accessor.dot
+//                        ^ punctuation.terminator
+
+public class Item implements Serializable {}
+// <- storage.modifier
+//     ^^^^^ storage.type
+//           ^^^^ entity.name.class
+//                ^^^^^^^^^^ keyword.declaration.implements
+//                           ^^^^^^^^^^^^ entity.other.inherited-class
+//                                        ^ punctuation.section.block.begin